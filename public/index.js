@@ -39,6 +39,26 @@ worker.onmessage = async (event) => {
       worker.postMessage({ ty: "hover", list: latestPick });
       break;
 
+    case "drillHoles":
+      window.drillHoles = data.list;
+      break;
+
+    case "drillHoleError":
+      console.error(data.error);
+      break;
+
+    case "explosives":
+      window.explosives = data.list;
+      break;
+
+    case "explosivesError":
+      console.error(data.error);
+      break;
+
+    case "surfaceError":
+      console.error(data.error);
+      break;
+
     default:
       break;
   }
@@ -221,6 +241,60 @@ window.set_worker_auto_animation = (needsAnimation) => {
   worker.postMessage({ ty: "autoAnimation", autoAnimation: needsAnimation });
 };
 
+// Blast controls, applied to both engine instances
+window.fire_blast = () => {
+  worker.postMessage({ ty: "fireBlast" });
+  if (window.fire_main_app_blast) window.fire_main_app_blast();
+};
+
+window.pause_blast = () => {
+  worker.postMessage({ ty: "pauseBlast" });
+  if (window.pause_main_app_blast) window.pause_main_app_blast();
+};
+
+window.reset_blast = () => {
+  worker.postMessage({ ty: "resetBlast" });
+  if (window.reset_main_app_blast) window.reset_main_app_blast();
+};
+
+// Blast design editing, `hole` is a plain object matching the Rust DrillHole
+window.add_drill_hole = (hole) => {
+  worker.postMessage({ ty: "addDrillHole", hole });
+};
+
+window.update_drill_hole = (id, hole) => {
+  worker.postMessage({ ty: "updateDrillHole", id, hole });
+};
+
+window.remove_drill_hole = (id) => {
+  worker.postMessage({ ty: "removeDrillHole", id });
+};
+
+window.list_drill_holes = () => {
+  worker.postMessage({ ty: "listDrillHoles" });
+};
+
+// Rebuild the bench under a survey file (a File or Blob), `format` is its extension and `spec`
+// matches the Rust SurfaceImport
+window.import_bench_surface = async (file, format, spec) => {
+  const bytes = await file.arrayBuffer();
+  worker.postMessage({ ty: "importBenchSurface", bytes, format, spec }, [bytes]);
+};
+
+// Replace all holes with a generated pattern, `pattern` matches the Rust BlastPattern
+window.generate_pattern = (pattern) => {
+  worker.postMessage({ ty: "generatePattern", pattern });
+};
+
+// Explosive products, the list arrives as an "explosives" message
+window.list_explosives = () => {
+  worker.postMessage({ ty: "listExplosives" });
+};
+
+window.load_explosive_library = (products) => {
+  worker.postMessage({ ty: "loadExplosiveLibrary", products });
+};
+
 function setContainerOpacity(opacity) {
   let ele = document.getElementById("worker-thread-container");
   ele.style.opacity = opacity;
@@ -10,6 +10,9 @@ import init, {
   set_selection,
   release_app,
   set_auto_animation,
+  fire_blast,
+  pause_blast,
+  reset_blast,
 } from "./bevy_in_main_thread.js";
 
 let appHandle = 0;
@@ -76,6 +79,18 @@ window.set_main_app_auto_animation = (needsAnimate) => {
   if (initFinished > 0) set_auto_animation(appHandle, needsAnimate);
 };
 
+window.fire_main_app_blast = () => {
+  if (initFinished > 0) fire_blast(appHandle);
+};
+
+window.pause_main_app_blast = () => {
+  if (initFinished > 0) pause_blast(appHandle);
+};
+
+window.reset_main_app_blast = () => {
+  if (initFinished > 0) reset_blast(appHandle);
+};
+
 function enterFrame(_dt) {
   // When the app is ready, execute the app's frame loop
   if (appHandle === 0 || isStoppedRunning) return;
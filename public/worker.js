@@ -14,6 +14,17 @@ const {
   left_bt_down,
   left_bt_up,
   set_auto_animation,
+  add_drill_hole,
+  update_drill_hole,
+  remove_drill_hole,
+  list_drill_holes,
+  generate_pattern,
+  import_bench_surface,
+  fire_blast,
+  pause_blast,
+  reset_blast,
+  list_explosives,
+  load_explosive_library,
 } = wasm_bindgen;
 
 // Initialize variables for app state
@@ -87,6 +98,47 @@ async function init_wasm_in_worker() {
         set_auto_animation(appHandle, data.autoAnimation);
         break;
 
+      case "fireBlast":
+        fire_blast(appHandle);
+        break;
+
+      case "pauseBlast":
+        pause_blast(appHandle);
+        break;
+
+      case "resetBlast":
+        reset_blast(appHandle);
+        break;
+
+      case "importBenchSurface":
+        // Survey file bytes, the bench is rebuilt under the surface
+        try {
+          import_bench_surface(
+            appHandle,
+            new Uint8Array(data.bytes),
+            data.format,
+            JSON.stringify(data.spec)
+          );
+        } catch (error) {
+          self.postMessage({ ty: "surfaceError", error: String(error) });
+        }
+        break;
+
+      case "listExplosives":
+      case "loadExplosiveLibrary":
+        // Explosive products for the per-hole product dropdown
+        editExplosives(data);
+        break;
+
+      case "addDrillHole":
+      case "updateDrillHole":
+      case "removeDrillHole":
+      case "listDrillHoles":
+      case "generatePattern":
+        // Blast design edits, the current hole list is always sent back
+        editDrillHoles(data);
+        break;
+
       default:
         break;
     }
@@ -154,6 +206,47 @@ function getPreparationState() {
   initFinished = is_preparation_completed(appHandle);
 }
 
+/** Apply a drill hole edit and report the resulting hole list (or the error) */
+function editDrillHoles(data) {
+  try {
+    switch (data.ty) {
+      case "addDrillHole":
+        add_drill_hole(appHandle, JSON.stringify(data.hole));
+        break;
+      case "updateDrillHole":
+        update_drill_hole(appHandle, data.id, JSON.stringify(data.hole));
+        break;
+      case "removeDrillHole":
+        remove_drill_hole(appHandle, data.id);
+        break;
+      case "generatePattern":
+        generate_pattern(appHandle, JSON.stringify(data.pattern));
+        break;
+    }
+    self.postMessage({
+      ty: "drillHoles",
+      list: JSON.parse(list_drill_holes(appHandle)),
+    });
+  } catch (error) {
+    self.postMessage({ ty: "drillHoleError", error: String(error) });
+  }
+}
+
+/** Load explosive products (if given) and report the full product list (or the error) */
+function editExplosives(data) {
+  try {
+    if (data.ty === "loadExplosiveLibrary") {
+      load_explosive_library(appHandle, JSON.stringify(data.products));
+    }
+    self.postMessage({
+      ty: "explosives",
+      list: JSON.parse(list_explosives(appHandle)),
+    });
+  } catch (error) {
+    self.postMessage({ ty: "explosivesError", error: String(error) });
+  }
+}
+
 /** Send ray pick results to the main thread */
 function send_pick_from_worker(pickList) {
   self.postMessage({ ty: "pick", list: pickList });
@@ -0,0 +1,210 @@
+use crate::bench_geometry::BenchGeometry;
+use crate::explosives::{HoleInterval, IntervalKind};
+use crate::useful_structs::DrillHole;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Sound pressure of 0 dBL in Pa
+const REFERENCE_PRESSURE: f32 = 20e-6;
+/// Horizontal directions searched for the free face around a deck
+const FACE_DIRECTIONS: usize = 16;
+
+/// Cube root scaled distance site law for airblast, P = K (D / ∛Q)^-a with P in kPa. K goes from
+/// `confined_constant` for a well confined deck to `unconfined_constant` for one venting freely
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AirblastSettings {
+    pub confined_constant: f32, // kPa, K of a fully stemmed deck behind a full burden
+    pub unconfined_constant: f32, // kPa, K of a deck open to the air
+    pub attenuation: f32,       // a, how fast the overpressure decays with scaled distance
+    pub stemming_diameters: f32, // hole diameters of stemming that fully confine a deck
+    pub burden_diameters: f32,  // hole diameters of rock to the free face that do the same
+}
+
+impl Default for AirblastSettings {
+    fn default() -> Self {
+        AirblastSettings {
+            confined_constant: 10.0,
+            unconfined_constant: 100.0,
+            attenuation: 1.45,
+            stemming_diameters: 25.0,
+            burden_diameters: 30.0,
+        }
+    }
+}
+
+impl AirblastSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.confined_constant > 0.0) {
+            return Err(format!(
+                "confined_constant must be positive, got {}",
+                self.confined_constant
+            ));
+        }
+        if !(self.unconfined_constant >= self.confined_constant) {
+            return Err(format!(
+                "unconfined_constant must be at least confined_constant, got {}",
+                self.unconfined_constant
+            ));
+        }
+        for (name, value) in [
+            ("attenuation", self.attenuation),
+            ("stemming_diameters", self.stemming_diameters),
+            ("burden_diameters", self.burden_diameters),
+        ] {
+            if !(value > 0.0) {
+                return Err(format!("{} must be positive, got {}", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// How well a deck is held in, from 0 (open to the air) to 1, limited by whichever of the
+    /// stemming above it and the burden to the face is shorter for the hole diameter
+    pub fn confinement(&self, diameter: f32, stemming: f32, face_burden: f32) -> f32 {
+        let stemming = stemming / (self.stemming_diameters * diameter);
+        let burden = face_burden / (self.burden_diameters * diameter);
+        stemming.min(burden).clamp(0.0, 1.0)
+    }
+
+    /// Site constant in kPa, interpolated on a log scale as both ends are an order of magnitude apart
+    pub fn site_constant(&self, confinement: f32) -> f32 {
+        self.unconfined_constant
+            * (self.confined_constant / self.unconfined_constant).powf(confinement)
+    }
+
+    /// Cube root scaled distance in m/kg^(1/3)
+    pub fn scaled_distance(&self, distance: f32, charge: f32) -> f32 {
+        distance / charge.cbrt()
+    }
+
+    /// Peak overpressure in Pa `distance` m from `charge` kg firing at once
+    pub fn overpressure(&self, distance: f32, charge: f32, confinement: f32) -> f32 {
+        // as for vibration the law doesn't hold right next to the charge
+        let scaled_distance = self.scaled_distance(distance.max(1.0), charge);
+        self.site_constant(confinement) * scaled_distance.powf(-self.attenuation) * 1000.0
+    }
+}
+
+/// Overpressure in Pa as a linear peak sound pressure level
+pub fn decibels(pressure: f32) -> f32 {
+    20.0 * (pressure / REFERENCE_PRESSURE).log10()
+}
+
+/// Length of inert stemming between the collar and the top of `deck`
+pub fn stemming_above(drill_hole: &DrillHole, deck: &HoleInterval) -> f32 {
+    drill_hole
+        .charge
+        .intervals
+        .iter()
+        .filter(|interval| interval.kind == IntervalKind::Stemming && interval.from < deck.from)
+        .map(|interval| interval.to.min(deck.from) - interval.from)
+        .sum()
+}
+
+/// Shortest horizontal distance from `point` out of the bench, searched in steps of the smallest
+/// block size up to `max_distance`
+pub fn face_burden(
+    geometry: &BenchGeometry,
+    resolution: &[f32; 3],
+    point: Vec3,
+    max_distance: f32,
+) -> f32 {
+    let (first, counts) = geometry.grid(resolution);
+    let size = Vec3::from(*resolution);
+    let min = first - size / 2.0;
+    let max = min + Vec3::new(counts[0] as f32, counts[1] as f32, counts[2] as f32) * size;
+    let inside = |p: Vec3| {
+        p.x >= min.x && p.x <= max.x && p.z >= min.z && p.z <= max.z && geometry.contains(p)
+    };
+    let step = size.x.min(size.z);
+    let mut burden = max_distance;
+    for i in 0..FACE_DIRECTIONS {
+        let angle = i as f32 * std::f32::consts::TAU / FACE_DIRECTIONS as f32;
+        let direction = Vec3::new(angle.cos(), 0.0, angle.sin());
+        let mut distance = step;
+        while distance < burden {
+            if !inside(point + direction * distance) {
+                burden = distance;
+                break;
+            }
+            distance += step;
+        }
+    }
+    burden
+}
+
+/// Predicted airblast at one monitor, from the deck and delay that give the loudest blast
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AirblastResult {
+    pub overpressure: f32,    // dBL
+    pub pressure: f32,        // Pa
+    pub time: f32,            // ms, nominal firing time of the first deck of that delay
+    pub charge: f32,          // kg firing in that delay
+    pub distance: f32,        // m to the deck
+    pub scaled_distance: f32, // m/kg^(1/3)
+    pub confinement: f32,     // of the deck, 0 open to 1 fully confined
+    pub limit: Option<f32>,   // dBL, the monitor's airblast_limit
+    pub exceeds_limit: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explosives::ExplosiveCharge;
+
+    #[test]
+    fn confinement_follows_the_shorter_of_stemming_and_burden() {
+        let settings = AirblastSettings::default();
+        // 25 and 30 diameters of a 100 mm hole
+        assert_eq!(settings.confinement(0.1, 2.5, 3.0), 1.0);
+        assert!((settings.confinement(0.1, 1.25, 3.0) - 0.5).abs() < 1e-6);
+        assert!((settings.confinement(0.1, 5.0, 0.75) - 0.25).abs() < 1e-6);
+        assert_eq!(settings.confinement(0.1, 0.0, 3.0), 0.0);
+        assert_eq!(settings.confinement(0.1, 10.0, 10.0), 1.0);
+    }
+
+    #[test]
+    fn site_constant_is_interpolated_on_a_log_scale() {
+        let settings = AirblastSettings::default();
+        assert!((settings.site_constant(1.0) - 10.0).abs() < 1e-4);
+        assert!((settings.site_constant(0.0) - 100.0).abs() < 1e-4);
+        assert!((settings.site_constant(0.5) - 31.6228).abs() < 1e-3);
+    }
+
+    #[test]
+    fn overpressure_site_law() {
+        let settings = AirblastSettings::default();
+        // 100 m from 1000 kg is 10 m/kg^(1/3), 10 kPa x 10^-1.45
+        let pressure = settings.overpressure(100.0, 1000.0, 1.0);
+        assert!((pressure - 354.813).abs() < 0.01);
+        assert!((decibels(pressure) - 144.979).abs() < 1e-3);
+        assert_eq!(
+            settings.overpressure(0.2, 1.0, 1.0),
+            settings.overpressure(1.0, 1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn stemming_above_the_deck() {
+        let mut drill_hole = DrillHole::new(Vec3::ZERO, 1.0, 9.0, 0.0);
+        drill_hole.charge = ExplosiveCharge::single_deck("ANFO", 6.0, 3.0, 9.0);
+        let (_, deck) = drill_hole.charge.decks().next().unwrap();
+        assert_eq!(stemming_above(&drill_hole, deck), 3.0);
+    }
+
+    #[test]
+    fn face_burden_to_the_nearest_side() {
+        let geometry = BenchGeometry::Box {
+            dimensions: [10.0, 10.0, 10.0],
+            position: [0.5, 0.5, 0.5],
+        };
+        let resolution = [1.0, 1.0, 1.0];
+        // 2.5 m from the x = 0 side, found in whole 1 m steps
+        let burden = face_burden(&geometry, &resolution, Vec3::new(2.5, 5.0, 5.0), 50.0);
+        assert_eq!(burden, 3.0);
+        // never further than asked for
+        let burden = face_burden(&geometry, &resolution, Vec3::new(5.0, 5.0, 5.0), 2.0);
+        assert_eq!(burden, 2.0);
+    }
+}
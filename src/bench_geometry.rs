@@ -0,0 +1,372 @@
+use crate::surface_import::SurfaceImport;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+fn default_face_angle() -> f32 {
+    75.0
+}
+
+/// A bench face surveyed in the pit
+///
+/// The bench lies to the left of the crest line seen from above (walking from the first crest
+/// point to the last, like blast_pattern's face line) and runs `width` m back from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchFace {
+    pub crest: Vec<Vec3>, // top edge of the face, the y of each point is the bench top there
+    #[serde(default)]
+    pub toe: Vec<Vec3>, // bottom edge of the face at floor level, derived from face_angle if empty
+    #[serde(default = "default_face_angle")]
+    pub face_angle: f32, // degrees from horizontal, only used without a toe line
+    pub floor: f32,       // elevation of the bench floor
+    pub width: f32,       // how far the bench runs back from the crest
+}
+
+// Horizontal signed distance of `point` from a polyline, positive to the left, and the polyline's
+// elevation at the closest point. None if the point is past either end of the line
+fn polyline_offset(line: &[Vec3], point: Vec3) -> Option<(f32, f32)> {
+    let flat = Vec3::new(point.x, 0.0, point.z);
+    let mut best: Option<(f32, f32, f32)> = None; // (distance, signed distance, elevation)
+    for (i, segment) in line.windows(2).enumerate() {
+        let start = Vec3::new(segment[0].x, 0.0, segment[0].z);
+        let end = Vec3::new(segment[1].x, 0.0, segment[1].z);
+        let along = end - start;
+        let t = (flat - start).dot(along) / along.length_squared();
+        if (i == 0 && t < 0.0) || (i == line.len() - 2 && t > 1.0) {
+            // off the end of the line
+            continue;
+        }
+        let t = t.clamp(0.0, 1.0);
+        let closest = start + along * t;
+        let distance = flat.distance(closest);
+        if best.map_or(true, |(best_distance, _, _)| distance < best_distance) {
+            let left = Vec3::Y.cross(along).normalize();
+            let elevation = segment[0].y + (segment[1].y - segment[0].y) * t;
+            best = Some((distance, (flat - closest).dot(left), elevation));
+        }
+    }
+    best.map(|(_, signed, elevation)| (signed, elevation))
+}
+
+impl BenchFace {
+    pub fn contains(&self, point: Vec3) -> bool {
+        let Some((crest_offset, top)) = polyline_offset(&self.crest, point) else {
+            return false;
+        };
+        if point.y < self.floor || point.y > top || crest_offset > self.width {
+            return false;
+        }
+        if self.toe.is_empty() {
+            // the face leans back from the toe at face_angle
+            crest_offset + (top - point.y) / self.face_angle.to_radians().tan() >= 0.0
+        } else {
+            let Some((toe_offset, _)) = polyline_offset(&self.toe, point) else {
+                return false;
+            };
+            // blend between the toe and crest lines with height
+            let t = (point.y - self.floor) / (top - self.floor);
+            toe_offset * (1.0 - t) + crest_offset * t >= 0.0
+        }
+    }
+
+    // corners of the box holding the whole bench
+    fn bounds(&self) -> (Vec3, Vec3) {
+        let mut min = Vec3::splat(f32::INFINITY);
+        let mut max = Vec3::splat(f32::NEG_INFINITY);
+        for point in self.crest.iter().chain(self.toe.iter()) {
+            min = min.min(*point);
+            max = max.max(*point);
+        }
+        // the bench runs `width` back from the crest and the face slopes out in front of it
+        let slope_run = (max.y - self.floor) / self.face_angle.to_radians().tan();
+        let reach = Vec3::new(1.0, 0.0, 1.0) * self.width.max(slope_run);
+        (
+            Vec3::new(min.x, self.floor, min.z) - reach,
+            Vec3::new(max.x, max.y, max.z) + reach,
+        )
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.crest.len() < 2 {
+            return Err("crest needs at least 2 points".to_string());
+        }
+        if self.toe.len() == 1 {
+            return Err("toe needs at least 2 points, or none to use face_angle".to_string());
+        }
+        for line in [&self.crest, &self.toe] {
+            if !line.iter().all(|point| point.is_finite()) {
+                return Err("crest and toe points must be finite".to_string());
+            }
+            for segment in line.windows(2) {
+                if segment[0].xz() == segment[1].xz() {
+                    return Err(format!(
+                        "crest and toe points must be apart horizontally, {} repeats",
+                        segment[0]
+                    ));
+                }
+            }
+        }
+        if !(self.face_angle > 0.0 && self.face_angle <= 90.0) {
+            return Err(format!(
+                "face_angle must be between 0 and 90 degrees, got {}",
+                self.face_angle
+            ));
+        }
+        if !self.crest.iter().all(|point| point.y > self.floor) {
+            return Err(format!(
+                "every crest point must be above the floor at {}",
+                self.floor
+            ));
+        }
+        if !(self.width > 0.0) {
+            return Err(format!("width must be positive, got {}", self.width));
+        }
+        Ok(())
+    }
+}
+
+/// A bench between a gridded upper surface and a flat floor, see surface_import for building one
+/// from survey files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchSurface {
+    pub origin: Vec3,   // x and z of the first sample, samples run along +x then +z
+    pub cell_size: f32, // m between samples
+    pub columns: usize, // samples along x
+    pub rows: usize,    // samples along z
+    pub heights: Vec<Option<f32>>, // elevation of every sample row by row, None where there's no data
+    pub floor: f32,                // elevation of the bench floor
+}
+
+impl BenchSurface {
+    // surface elevation of the sample closest to `point`
+    pub fn height_at(&self, point: Vec3) -> Option<f32> {
+        let column = ((point.x - self.origin.x) / self.cell_size).round();
+        let row = ((point.z - self.origin.z) / self.cell_size).round();
+        if column < 0.0 || row < 0.0 {
+            return None;
+        }
+        let (column, row) = (column as usize, row as usize);
+        if column >= self.columns || row >= self.rows {
+            return None;
+        }
+        self.heights[row * self.columns + column]
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        match self.height_at(point) {
+            Some(height) => point.y >= self.floor && point.y <= height,
+            None => false,
+        }
+    }
+
+    fn bounds(&self) -> (Vec3, Vec3) {
+        let top = self
+            .heights
+            .iter()
+            .flatten()
+            .fold(self.floor, |a, b| a.max(*b));
+        let min = Vec3::new(self.origin.x, self.floor, self.origin.z);
+        let max = Vec3::new(
+            self.origin.x + self.columns.saturating_sub(1) as f32 * self.cell_size,
+            top,
+            self.origin.z + self.rows.saturating_sub(1) as f32 * self.cell_size,
+        );
+        (min, max)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.cell_size > 0.0) {
+            return Err(format!(
+                "cell_size must be positive, got {}",
+                self.cell_size
+            ));
+        }
+        if self.heights.len() != self.columns * self.rows {
+            return Err(format!(
+                "{} heights given for a {} x {} grid",
+                self.heights.len(),
+                self.columns,
+                self.rows
+            ));
+        }
+        if !(self.origin.is_finite() && self.floor.is_finite()) {
+            return Err("origin and floor must be finite".to_string());
+        }
+        if !self
+            .heights
+            .iter()
+            .flatten()
+            .all(|height| height.is_finite())
+        {
+            return Err("heights must be finite".to_string());
+        }
+        if !self
+            .heights
+            .iter()
+            .flatten()
+            .any(|height| *height > self.floor)
+        {
+            return Err(format!(
+                "no part of the surface is above the floor at {}",
+                self.floor
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The volume `setup_bench` fills with blocks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BenchGeometry {
+    /// Axis aligned box, `position` is the center of its first block
+    Box {
+        dimensions: [f32; 3],
+        position: [f32; 3],
+    },
+    /// Bench behind a surveyed crest (and toe) line
+    Face(BenchFace),
+    /// Bench under a gridded survey surface
+    Surface(BenchSurface),
+    /// Survey file to turn into a Surface, only valid until the scenario has been loaded
+    Import(SurfaceImport),
+}
+
+impl BenchGeometry {
+    /// Center of the first voxel and the number of voxels along x, y, z for blocks of `resolution`
+    pub fn grid(&self, resolution: &[f32; 3]) -> (Vec3, [usize; 3]) {
+        let resolution = Vec3::from(*resolution);
+        match self {
+            BenchGeometry::Box {
+                dimensions,
+                position,
+            } => {
+                let counts = Vec3::from(*dimensions) / resolution;
+                (
+                    Vec3::from(*position),
+                    [counts.x as usize, counts.y as usize, counts.z as usize],
+                )
+            }
+            BenchGeometry::Face(face) => Self::bounds_grid(face.bounds(), resolution),
+            BenchGeometry::Surface(surface) => Self::bounds_grid(surface.bounds(), resolution),
+            BenchGeometry::Import(_) => (Vec3::ZERO, [0, 0, 0]),
+        }
+    }
+
+    fn bounds_grid((min, max): (Vec3, Vec3), resolution: Vec3) -> (Vec3, [usize; 3]) {
+        let counts = ((max - min) / resolution).ceil().max(Vec3::ONE);
+        (
+            min + resolution / 2.0,
+            [counts.x as usize, counts.y as usize, counts.z as usize],
+        )
+    }
+
+    /// Whether a voxel centered on `point` belongs to the bench
+    pub fn contains(&self, point: Vec3) -> bool {
+        match self {
+            BenchGeometry::Box { .. } => true,
+            BenchGeometry::Face(face) => face.contains(point),
+            BenchGeometry::Surface(surface) => surface.contains(point),
+            BenchGeometry::Import(_) => false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            BenchGeometry::Box {
+                dimensions,
+                position,
+            } => {
+                if !dimensions.iter().all(|dim| dim.is_finite() && *dim > 0.0) {
+                    return Err(format!(
+                        "box dimensions must be positive, got {:?}",
+                        dimensions
+                    ));
+                }
+                if !position.iter().all(|coord| coord.is_finite()) {
+                    return Err("box position must be finite".to_string());
+                }
+                Ok(())
+            }
+            BenchGeometry::Face(face) => face.validate(),
+            BenchGeometry::Surface(surface) => surface.validate(),
+            BenchGeometry::Import(import) => Err(format!(
+                "surface file '{}' has not been loaded, import it natively or with import_bench_surface",
+                import.path
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 10 m high crest along x, the bench running 5 m back towards -z
+    fn face(toe: Vec<Vec3>) -> BenchFace {
+        BenchFace {
+            crest: vec![Vec3::new(0.0, 10.0, 0.0), Vec3::new(10.0, 10.0, 0.0)],
+            toe,
+            face_angle: 45.0,
+            floor: 0.0,
+            width: 5.0,
+        }
+    }
+
+    #[test]
+    fn face_slopes_out_from_the_crest() {
+        let face = BenchGeometry::Face(face(Vec::new()));
+        assert!(face.validate().is_ok());
+        // at 45 degrees the face is 5 m out in front of the crest halfway down
+        assert!(face.contains(Vec3::new(5.0, 5.0, 4.5)));
+        assert!(!face.contains(Vec3::new(5.0, 5.0, 5.5)));
+        assert!(face.contains(Vec3::new(5.0, 5.0, -4.5)));
+        // behind the bench, above the crest, below the floor, past the end of the crest
+        assert!(!face.contains(Vec3::new(5.0, 5.0, -5.5)));
+        assert!(!face.contains(Vec3::new(5.0, 10.5, -1.0)));
+        assert!(!face.contains(Vec3::new(5.0, -0.5, -1.0)));
+        assert!(!face.contains(Vec3::new(-1.0, 5.0, -1.0)));
+    }
+
+    #[test]
+    fn toe_line_overrides_the_face_angle() {
+        let toe = vec![Vec3::new(0.0, 0.0, 4.0), Vec3::new(10.0, 0.0, 4.0)];
+        let face = BenchGeometry::Face(face(toe));
+        assert!(face.contains(Vec3::new(5.0, 0.5, 3.5)));
+        // halfway up, halfway between toe and crest
+        assert!(face.contains(Vec3::new(5.0, 5.0, 1.5)));
+        assert!(!face.contains(Vec3::new(5.0, 5.0, 2.5)));
+    }
+
+    #[test]
+    fn surface_holds_what_is_under_it() {
+        let surface = BenchGeometry::Surface(BenchSurface {
+            origin: Vec3::ZERO,
+            cell_size: 1.0,
+            columns: 2,
+            rows: 2,
+            heights: vec![Some(5.0), None, Some(3.0), Some(4.0)],
+            floor: 0.0,
+        });
+        assert!(surface.validate().is_ok());
+        assert!(surface.contains(Vec3::new(0.0, 4.0, 0.0)));
+        assert!(!surface.contains(Vec3::new(0.0, 6.0, 0.0)));
+        // nearest sample is at column 0, row 1
+        assert!(surface.contains(Vec3::new(0.4, 2.0, 0.6)));
+        assert!(!surface.contains(Vec3::new(0.4, 3.5, 0.6)));
+        // no data, off the grid, below the floor
+        assert!(!surface.contains(Vec3::new(1.0, 1.0, 0.0)));
+        assert!(!surface.contains(Vec3::new(-1.0, 1.0, 0.0)));
+        assert!(!surface.contains(Vec3::new(0.0, -1.0, 0.0)));
+    }
+
+    #[test]
+    fn boxes_are_always_full() {
+        let bench = BenchGeometry::Box {
+            dimensions: [4.0, 2.0, 3.0],
+            position: [1.0, 0.5, 1.0],
+        };
+        assert!(bench.contains(Vec3::new(100.0, 100.0, 100.0)));
+        let (first, counts) = bench.grid(&[0.5, 0.5, 0.5]);
+        assert_eq!(first, Vec3::new(1.0, 0.5, 1.0));
+        assert_eq!(counts, [8, 4, 6]);
+    }
+}
@@ -25,8 +25,21 @@ use std::cmp::{max, min};
 // Elliot Imports
 use bevy_flycam::FlyCam;
 use bevy_flycam::NoCameraPlayerPlugin;
+use crate::blast_clock::*;
+use crate::bonds::*;
 use crate::drill_hole_go_boom::*;
+use crate::drill_hole_visuals::*;
+use crate::explosives::*;
+use crate::flyrock::*;
+use crate::blast_report::*;
+use crate::fragmentation::*;
+use crate::initiation::*;
+use crate::joints::*;
+use crate::kuz_ram::*;
+use crate::muckpile::*;
+use crate::scenario::*;
 use crate::setup::*;
+use crate::simulation::*;
 use crate::useful_structs::*;
 
 
@@ -61,31 +74,13 @@ struct TouchCamera {
     // let drill_holes_vec: DrillHoles = DrillHoles(vec![drill_hole_1, drill_hole_2]);
 
 
-// Initialize the application
+// Initialize the application with the default scenario
 pub(crate) fn init_app() -> WorkerApp {
-    let bench_dimensions: [f32; 3] = [20.0, 4.0, 10.0];
-    let bench_resolution: [f32; 3] = [0.5, 0.5, 0.5];
-    let bench_position: [f32; 3] = [0.0, 0.1, 0.0];
-    let drill_hole_1_position: (f32, f32) = (6.0, 5.0);
-    let drill_hole_1_radius: f32 = 1.0;
-    let drill_hole_1_height: f32 = 3.6;
-    let drill_hole_2_position: (f32, f32) = (14.0, 5.0);
-    let drill_hole_2_radius: f32 = 1.0;
-    let drill_hole_2_height: f32 = 3.6;
-
-    let drill_hole_1 = DrillHole {
-        position: Vec3::new(drill_hole_1_position.0, 0.0, drill_hole_1_position.1),
-        radius: drill_hole_1_radius,
-        height: drill_hole_1_height,
-        timing: 0.0,
-    };
-    let drill_hole_2 = DrillHole {
-        position: Vec3::new(drill_hole_2_position.0, 0.0, drill_hole_2_position.1),
-        radius: drill_hole_2_radius,
-        height: drill_hole_2_height,
-        timing: 2.0,
-    };
-    let drill_holes_vec: DrillHoles = DrillHoles(vec![drill_hole_1, drill_hole_2]);
+    init_app_with_scenario(BlastScenario::default())
+}
+
+// Initialize the application from a validated blast scenario
+pub fn init_app_with_scenario(scenario: BlastScenario) -> WorkerApp {
     let mut app = App::new();
 
     // Configure default plugins
@@ -112,41 +107,94 @@ pub(crate) fn init_app() -> WorkerApp {
 
     app.add_systems(Startup, setup_graphics)
         .add_systems(Startup, setup_ground)
-        .insert_resource(drill_holes_vec.clone())
+        .insert_resource(scenario.drill_holes.clone())
+        .insert_resource(scenario.explosive_library())
+        .insert_resource(scenario.rock_mass())
+        .insert_resource(SimulationRng::new(scenario.seed))
+        .insert_resource(scenario.timestep_mode())
+        .insert_resource(scenario)
         .add_systems(
             Startup,
-            move |commands: Commands,
-                  meshes: ResMut<Assets<Mesh>>,
-                  materials: ResMut<Assets<StandardMaterial>>,
-                  drill_holes_vec: Res<DrillHoles>| {
+            |commands: Commands,
+             meshes: ResMut<Assets<Mesh>>,
+             materials: ResMut<Assets<StandardMaterial>>,
+             scenario: Res<BlastScenario>,
+             drill_holes_vec: Res<DrillHoles>,
+             rock_mass: Res<RockMass>,
+             mut rng: ResMut<SimulationRng>| {
                 setup_bench(
                     commands,
                     meshes,
                     materials,
-                    &bench_dimensions,
-                    &bench_resolution,
-                    &bench_position,
+                    &scenario.bench_geometry(),
+                    &scenario.bench_resolution,
                     &drill_holes_vec,
+                    scenario.carve_drill_holes,
+                    scenario.adaptive.as_ref(),
+                    scenario.voronoi.as_ref(),
+                    &rock_mass,
+                    &scenario.bonds,
+                    &mut rng.rng,
                 );
             },
         )
         .add_systems(Update, handle_touch_input)
+        .add_systems(Update, draw_drill_holes)
+        .add_systems(Update, update_charge_columns)
+        .add_event::<DetonationEvent>()
+        .add_event::<BlastCommand>()
+        .init_resource::<InitiationPlan>()
+        .init_resource::<BlastClock>()
+        .init_resource::<BrokenBonds>()
+        .init_resource::<FragmentationReport>()
+        .init_resource::<FragmentationPrediction>()
+        .init_resource::<MuckpileReport>()
+        .add_systems(Update, muckpile_system.after(fragmentation_system))
+        .add_systems(Update, draw_muckpile_sections)
+        .init_resource::<FlyrockTracker>()
+        .init_resource::<FlyrockReport>()
+        .add_systems(Update, draw_flyrock)
+        .init_resource::<BlastReport>()
+        .add_systems(Update, blast_report_system)
+        .add_systems(Update, draw_monitors)
         .add_systems(
             Update,
-            move |commands: Commands,
-                  rapier_context: ResMut<RapierContext>,
-                  time: Res<Time>,
-                  drill_holes: Res<DrillHoles>,
-                  entity_query: Query<(&Transform, Option<&mut ExternalImpulse>)>| {
-                drill_hole_go_boom_system(
-                    commands,
-                    rapier_context,
-                    drill_holes,
-                    time,
-                    64.0,
-                    entity_query,
-                );
-            },
+            fragmentation_prediction_system.before(fragmentation_system),
+        )
+        .add_systems(Update, blast_keyboard_input.before(blast_command_system))
+        .add_systems(
+            Update,
+            (
+                blast_command_system,
+                advance_blast_clock,
+                initiation_system,
+                |commands: Commands,
+                 rapier_context: ResMut<RapierContext>,
+                 scenario: Res<BlastScenario>,
+                 drill_holes: Res<DrillHoles>,
+                 explosive_library: Res<ExplosiveLibrary>,
+                 detonations: EventReader<DetonationEvent>,
+                 entity_query: Query<(
+                    &Transform,
+                    Option<&mut ExternalImpulse>,
+                    Option<&mut Block>,
+                )>| {
+                    drill_hole_go_boom_system(
+                        commands,
+                        rapier_context,
+                        drill_holes,
+                        explosive_library,
+                        detonations,
+                        scenario.impulse_constant,
+                        scenario.attenuation,
+                        entity_query,
+                    );
+                },
+                bond_breaking_system,
+                flyrock_system,
+                fragmentation_system,
+            )
+                .chain(),
         );
 
     WorkerApp::new(app)
@@ -0,0 +1,188 @@
+use crate::bonds::BrokenBonds;
+use crate::initiation::InitiationPlan;
+use crate::joints::RockMass;
+use crate::scenario::BlastScenario;
+use crate::setup::*;
+use crate::simulation::*;
+use crate::useful_structs::*;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum BlastState {
+    /// Waiting for the fire command, no hole can go off
+    #[default]
+    Armed,
+    /// The clock is running and holes fire as their delays are reached
+    Firing,
+    /// Clock and physics are frozen mid-blast
+    Paused,
+}
+
+/// Time since the user fired the blast, independent of how long the app has been running
+#[derive(Debug, Default, Resource, Serialize)]
+pub struct BlastClock {
+    pub state: BlastState,
+    pub elapsed: f32, // milliseconds since the blast was fired, pauses excluded
+}
+
+impl BlastClock {
+    // start (or resume) the blast, returns false if it is already running
+    pub fn fire(&mut self) -> bool {
+        if self.state == BlastState::Firing {
+            return false;
+        }
+        self.state = BlastState::Firing;
+        true
+    }
+
+    pub fn pause(&mut self) -> bool {
+        if self.state != BlastState::Firing {
+            return false;
+        }
+        self.state = BlastState::Paused;
+        true
+    }
+
+    // back to zero and armed
+    pub fn reset(&mut self) {
+        self.state = BlastState::Armed;
+        self.elapsed = 0.0;
+    }
+}
+
+/// Commands sent by the FFI or the keyboard to control the blast
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Event)]
+pub enum BlastCommand {
+    Fire,
+    Pause,
+    /// Put the bench back to its pre-blast state and re-arm
+    Reset,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn blast_command_system(
+    mut commands: Commands,
+    meshes: ResMut<Assets<Mesh>>,
+    materials: ResMut<Assets<StandardMaterial>>,
+    mut blast_commands: EventReader<BlastCommand>,
+    mut clock: ResMut<BlastClock>,
+    mut plan: ResMut<InitiationPlan>,
+    mut broken_bonds: ResMut<BrokenBonds>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut timestep_mode: ResMut<TimestepMode>,
+    mut rng: ResMut<SimulationRng>,
+    scenario: Res<BlastScenario>,
+    drill_holes: Res<DrillHoles>,
+    rock_mass: Res<RockMass>,
+    bench_blocks: Query<Entity, With<BenchBlock>>,
+) {
+    let mut reset = false;
+    for blast_command in blast_commands.read() {
+        match blast_command {
+            BlastCommand::Fire => {
+                if clock.fire() {
+                    rapier_config.physics_pipeline_active = true;
+                    info!("Blast fired");
+                }
+            }
+            BlastCommand::Pause => {
+                if clock.pause() {
+                    rapier_config.physics_pipeline_active = false;
+                    info!("Blast paused at {} ms", clock.elapsed);
+                }
+            }
+            BlastCommand::Reset => reset = true,
+        }
+    }
+
+    if reset {
+        clock.reset();
+        // a seeded scenario starts its random sequence over, the bench then draws from it before
+        // the detonators just like at startup
+        rng.reset(scenario.seed);
+        *timestep_mode = scenario.timestep_mode();
+        broken_bonds.0.clear();
+        rapier_config.physics_pipeline_active = true;
+
+        for entity in bench_blocks.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        setup_bench(
+            commands,
+            meshes,
+            materials,
+            &scenario.bench_geometry(),
+            &scenario.bench_resolution,
+            &drill_holes,
+            scenario.carve_drill_holes,
+            scenario.adaptive.as_ref(),
+            scenario.voronoi.as_ref(),
+            &rock_mass,
+            &scenario.bonds,
+            &mut rng.rng,
+        );
+        // forget which holes went off and draw new scatter for the next try
+        plan.detonated.clear();
+        plan.rebuild(&drill_holes, &mut rng.rng);
+        info!("Blast reset");
+    }
+}
+
+pub fn advance_blast_clock(
+    time: Res<Time>,
+    timestep_mode: Res<TimestepMode>,
+    mut clock: ResMut<BlastClock>,
+) {
+    if clock.state == BlastState::Firing {
+        clock.elapsed += physics_step_seconds(&timestep_mode, &time) * 1000.0;
+    }
+}
+
+// Native controls: F fires, P pauses, R resets the bench
+pub fn blast_keyboard_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut blast_commands: EventWriter<BlastCommand>,
+) {
+    if keys.just_pressed(KeyCode::KeyF) {
+        blast_commands.send(BlastCommand::Fire);
+    }
+    if keys.just_pressed(KeyCode::KeyP) {
+        blast_commands.send(BlastCommand::Pause);
+    }
+    if keys.just_pressed(KeyCode::KeyR) {
+        blast_commands.send(BlastCommand::Reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fire_pause_and_reset() {
+        let mut clock = BlastClock::default();
+        assert_eq!(clock.state, BlastState::Armed);
+        // nothing to pause before the blast is fired
+        assert!(!clock.pause());
+        assert_eq!(clock.state, BlastState::Armed);
+
+        assert!(clock.fire());
+        assert_eq!(clock.state, BlastState::Firing);
+        assert!(!clock.fire());
+        clock.elapsed = 120.0;
+
+        assert!(clock.pause());
+        assert_eq!(clock.state, BlastState::Paused);
+        assert!(!clock.pause());
+        // firing again resumes where it was paused
+        assert!(clock.fire());
+        assert_eq!(clock.state, BlastState::Firing);
+        assert_eq!(clock.elapsed, 120.0);
+
+        clock.reset();
+        assert_eq!(clock.state, BlastState::Armed);
+        assert_eq!(clock.elapsed, 0.0);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::explosives::ExplosiveCharge;
+use crate::useful_structs::*;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on the holes a pattern may generate, a burden typo shouldn't freeze the page
+const MAX_PATTERN_HOLES: usize = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimingKind {
+    /// Rows fire one after the other, holes along each row from the start of the face
+    #[default]
+    RowByRow,
+    /// Initiated from the middle of the face and spreading to both ends
+    V,
+    /// Diagonal firing lines from the start of the face, row_delay is not used
+    Echelon,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimingPlan {
+    pub kind: TimingKind,
+    pub hole_delay: f32, // ms between neighbouring holes along a row
+    pub row_delay: f32,  // ms between rows, not used by Echelon
+}
+
+impl Default for TimingPlan {
+    fn default() -> Self {
+        TimingPlan {
+            kind: TimingKind::RowByRow,
+            hole_delay: 17.0,
+            row_delay: 42.0,
+        }
+    }
+}
+
+fn default_pattern_product() -> String {
+    "ANFO".to_string()
+}
+
+fn default_pattern_dip() -> f32 {
+    90.0
+}
+
+fn default_pattern_radius() -> f32 {
+    1.0
+}
+
+fn default_pattern_diameter() -> f32 {
+    0.115
+}
+
+/// A burden x spacing grid of holes laid out behind a face line
+///
+/// The bench lies to the left of the line from `face_start` to `face_end` seen from above, the
+/// first row is one burden back from the face and every further row one burden behind it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlastPattern {
+    pub face_start: Vec3, // start of the face line, at collar elevation
+    pub face_end: Vec3,   // end of the face line
+    pub burden: f32,      // m between rows, and from the face to the first row
+    pub spacing: f32,     // m between holes along a row
+    pub rows: u32,
+    #[serde(default)]
+    pub stagger: bool, // shift every other row by half a spacing
+    pub depth: f32, // hole length from the collar down to grade
+    #[serde(default)]
+    pub subdrill: f32,
+    #[serde(default = "default_pattern_dip")]
+    pub dip: f32, // holes are angled towards the face when less than 90
+    #[serde(default = "default_pattern_radius")]
+    pub radius: f32, // area of effect of every hole
+    #[serde(default = "default_pattern_diameter")]
+    pub diameter: f32,
+    pub stemming: f32, // m of stemming on top of a single deck filling the rest of the hole
+    #[serde(default = "default_pattern_product")]
+    pub product: String,
+    #[serde(default)]
+    pub timing: TimingPlan,
+}
+
+impl BlastPattern {
+    fn face_length(&self) -> f32 {
+        self.face_start.distance(self.face_end)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.face_start.is_finite() && self.face_end.is_finite()) {
+            return Err("face_start and face_end must be finite".to_string());
+        }
+        let along = self.face_end - self.face_start;
+        if !(Vec3::new(along.x, 0.0, along.z).length() > 0.0) {
+            return Err("face_start and face_end must be apart horizontally".to_string());
+        }
+        if !(self.burden > 0.0) {
+            return Err(format!("burden must be positive, got {}", self.burden));
+        }
+        if !(self.spacing > 0.0) {
+            return Err(format!("spacing must be positive, got {}", self.spacing));
+        }
+        if self.rows == 0 {
+            return Err("rows must be at least 1".to_string());
+        }
+        if !(self.stemming >= 0.0 && self.stemming < self.depth + self.subdrill) {
+            return Err(format!(
+                "stemming must be shorter than the {} m hole, got {}",
+                self.depth + self.subdrill,
+                self.stemming
+            ));
+        }
+        // echelon firing lines only step by the hole delay
+        let row_delay = match self.timing.kind {
+            TimingKind::Echelon => 0.0,
+            _ => self.timing.row_delay,
+        };
+        if !(self.timing.hole_delay >= 0.0 && row_delay >= 0.0) {
+            return Err("timing delays must not be negative".to_string());
+        }
+        // generate() puts a hole at both ends of the face, with the same tolerance. Counted in f64
+        // so a tiny spacing or huge row count can't wrap around
+        let per_row = ((self.face_length() as f64 + 1e-3) / self.spacing as f64).floor() + 1.0;
+        let hole_count = per_row * self.rows as f64;
+        if !(hole_count <= MAX_PATTERN_HOLES as f64) {
+            return Err(format!(
+                "pattern would drill {} holes, the limit is {}",
+                hole_count, MAX_PATTERN_HOLES
+            ));
+        }
+        // the rest is checked on every generated hole
+        self.hole(Vec3::ZERO, 0.0, 0.0).validate()
+    }
+
+    fn hole(&self, collar: Vec3, azimuth: f32, timing: f32) -> DrillHole {
+        let drilled_length = self.depth + self.subdrill;
+        let mut drill_hole = DrillHole::new(collar, self.radius, self.depth, timing);
+        drill_hole.azimuth = azimuth;
+        drill_hole.dip = self.dip;
+        drill_hole.subdrill = self.subdrill;
+        drill_hole.diameter = self.diameter;
+        drill_hole.charge = ExplosiveCharge::single_deck(
+            &self.product,
+            drilled_length - self.stemming,
+            self.stemming,
+            drilled_length,
+        );
+        drill_hole
+    }
+
+    // firing time of a hole `along` m from the start of the face in row `row`
+    fn firing_time(&self, along: f32, row: u32) -> f32 {
+        let timing = &self.timing;
+        let holes_along = along / self.spacing;
+        match timing.kind {
+            TimingKind::RowByRow => row as f32 * timing.row_delay + holes_along * timing.hole_delay,
+            TimingKind::V => {
+                let holes_from_middle = (along - self.face_length() / 2.0).abs() / self.spacing;
+                row as f32 * timing.row_delay + holes_from_middle * timing.hole_delay
+            }
+            TimingKind::Echelon => (holes_along + row as f32) * timing.hole_delay,
+        }
+    }
+
+    /// Lay out the holes, ids are assigned once they are added to the scenario or app
+    pub fn generate(&self) -> Result<Vec<DrillHole>, String> {
+        self.validate()?;
+        let face_length = self.face_length();
+        let along = (self.face_end - self.face_start).normalize();
+        let horizontal = Vec3::new(along.x, 0.0, along.z).normalize();
+        // into the bench, holes point the other way so angled holes lean towards the face
+        let back = Vec3::Y.cross(horizontal);
+        let azimuth = (-back.x).atan2(back.z).to_degrees().rem_euclid(360.0);
+
+        let mut drill_holes = Vec::new();
+        for row in 0..self.rows {
+            let offset = if self.stagger && row % 2 == 1 {
+                self.spacing / 2.0
+            } else {
+                0.0
+            };
+            let mut distance = offset;
+            while distance <= face_length + 1e-3 {
+                let collar =
+                    self.face_start + along * distance + back * (self.burden * (row + 1) as f32);
+                let timing = self.firing_time(distance, row);
+                drill_holes.push(self.hole(collar, azimuth, timing));
+                distance += self.spacing;
+            }
+        }
+        Ok(drill_holes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern() -> BlastPattern {
+        BlastPattern {
+            face_start: Vec3::ZERO,
+            face_end: Vec3::new(10.0, 0.0, 0.0),
+            burden: 3.0,
+            spacing: 2.5,
+            rows: 2,
+            stagger: false,
+            depth: 10.0,
+            subdrill: 1.0,
+            dip: default_pattern_dip(),
+            radius: default_pattern_radius(),
+            diameter: default_pattern_diameter(),
+            stemming: 3.0,
+            product: default_pattern_product(),
+            timing: TimingPlan::default(),
+        }
+    }
+
+    #[test]
+    fn holes_at_both_ends_of_the_face() {
+        let drill_holes = pattern().generate().unwrap();
+        // 0, 2.5, 5, 7.5 and 10 m along the face in both rows
+        assert_eq!(drill_holes.len(), 10);
+        // the bench is left of the face line, -z here, a burden back per row
+        assert_eq!(drill_holes[0].collar, Vec3::new(0.0, 0.0, -3.0));
+        assert_eq!(drill_holes[4].collar, Vec3::new(10.0, 0.0, -3.0));
+        assert_eq!(drill_holes[9].collar, Vec3::new(10.0, 0.0, -6.0));
+        assert!((drill_holes[0].azimuth - 180.0).abs() < 1e-3);
+        assert_eq!(drill_holes[0].subdrill, 1.0);
+    }
+
+    #[test]
+    fn stagger_shifts_every_other_row() {
+        let mut pattern = pattern();
+        pattern.stagger = true;
+        let drill_holes = pattern.generate().unwrap();
+        // 1.25, 3.75, 6.25 and 8.75 m in the second row
+        assert_eq!(drill_holes.len(), 9);
+        assert_eq!(drill_holes[5].collar, Vec3::new(1.25, 0.0, -6.0));
+    }
+
+    #[test]
+    fn row_by_row_timing() {
+        let drill_holes = pattern().generate().unwrap();
+        // second row, third hole: 42 ms row delay + 2 x 17 ms hole delay
+        assert!((drill_holes[7].timing - 76.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn v_timing_starts_in_the_middle() {
+        let mut pattern = pattern();
+        pattern.timing.kind = TimingKind::V;
+        let drill_holes = pattern.generate().unwrap();
+        assert_eq!(drill_holes[2].timing, 0.0);
+        assert!((drill_holes[0].timing - 34.0).abs() < 1e-3);
+        assert!((drill_holes[4].timing - 34.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn hole_limit_counts_both_ends() {
+        let mut pattern = pattern();
+        // 1999 spacings along the face are 2000 holes, the limit
+        pattern.face_end = Vec3::new(1999.0 * 2.5, 0.0, 0.0);
+        pattern.rows = 1;
+        assert!(pattern.validate().is_ok());
+        pattern.face_end.x += 2.5;
+        assert!(pattern.validate().is_err());
+    }
+
+    #[test]
+    fn hole_count_does_not_wrap() {
+        let mut tiny_spacing = pattern();
+        tiny_spacing.spacing = 1e-30;
+        assert!(tiny_spacing.validate().is_err());
+        let mut many_rows = pattern();
+        many_rows.rows = u32::MAX;
+        assert!(many_rows.validate().is_err());
+    }
+
+    #[test]
+    fn echelon_ignores_the_row_delay() {
+        let mut pattern = pattern();
+        pattern.timing.kind = TimingKind::Echelon;
+        pattern.timing.row_delay = -1.0;
+        let drill_holes = pattern.generate().unwrap();
+        // second row, first hole: one hole delay behind the first row
+        assert!((drill_holes[5].timing - 17.0).abs() < 1e-3);
+        pattern.timing.kind = TimingKind::RowByRow;
+        assert!(pattern.validate().is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::airblast::*;
+use crate::bench_geometry::BenchGeometry;
+use crate::explosives::ExplosiveLibrary;
+use crate::initiation::DeckId;
+use crate::scenario::BlastScenario;
+use crate::useful_structs::DrillHoles;
+use crate::vibration::*;
+use bevy::color::palettes::css::{LIME, RED};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A point around the blast its effects are predicted at, e.g. a nearby building
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Monitor {
+    pub name: String,
+    pub position: Vec3,
+    #[serde(default)]
+    pub ppv_limit: Option<f32>, // mm/s allowed here, flagged in the report when exceeded
+    #[serde(default)]
+    pub airblast_limit: Option<f32>, // dBL allowed here, likewise
+}
+
+impl Monitor {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.position.is_finite() {
+            return Err("position must be finite".to_string());
+        }
+        if let Some(limit) = self.ppv_limit {
+            if !(limit > 0.0) {
+                return Err(format!("ppv_limit must be positive, got {}", limit));
+            }
+        }
+        if let Some(limit) = self.airblast_limit {
+            if !(limit > 0.0) {
+                return Err(format!("airblast_limit must be positive, got {}", limit));
+            }
+        }
+        Ok(())
+    }
+}
+
+// a charge deck at its nominal firing time
+struct FiredDeck {
+    deck: DeckId,
+    time: f32, // ms
+    mass: f32, // kg
+    position: Vec3,
+    confinement: f32, // see AirblastSettings::confinement
+}
+
+/// Decks firing within the delay window of the first one, the site laws see them as one charge
+#[derive(Debug, Clone, Serialize)]
+pub struct DelayGroup {
+    pub time: f32, // ms, nominal firing time of the first deck
+    pub span: f32, // ms between the first and last deck
+    pub decks: Vec<DeckId>,
+    pub charge: f32,       // kg firing together
+    pub largest_deck: f32, // kg, what the delay would weigh with the decks spread out
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorReport {
+    pub name: String,
+    pub position: Vec3,
+    pub vibration: VibrationResult,
+    pub airblast: AirblastResult,
+}
+
+/// What the loaded holes are expected to do around the site, worked out from the design before
+/// firing. Times are the nominal ones, detonator scatter is left out
+#[derive(Debug, Clone, Default, Resource, Serialize)]
+pub struct BlastReport {
+    pub decks: usize,
+    pub total_charge: f32,         // kg
+    pub max_charge_per_delay: f32, // kg, the maximum instantaneous charge
+    pub max_charge_time: f32,      // ms, when that delay starts
+    pub overlaps: Vec<DelayGroup>, // delays holding more than one deck
+    pub monitors: Vec<MonitorReport>,
+    pub within_limits: bool, // no monitor is predicted over any of its limits
+}
+
+impl BlastReport {
+    pub fn build(
+        drill_holes: &DrillHoles,
+        library: &ExplosiveLibrary,
+        geometry: &BenchGeometry,
+        resolution: &[f32; 3],
+        monitors: &[Monitor],
+        vibration: &VibrationSettings,
+        airblast: &AirblastSettings,
+    ) -> Self {
+        let mut decks = Vec::new();
+        for drill_hole in drill_holes.0.iter() {
+            for (index, deck) in drill_hole.charge.decks() {
+                let Some(product) = library.get(&deck.product) else {
+                    continue;
+                };
+                let position = drill_hole.point_at_depth((deck.from + deck.to) / 2.0);
+                // the face only matters as far out as it can still let the deck vent
+                let burden = face_burden(
+                    geometry,
+                    resolution,
+                    position,
+                    airblast.burden_diameters * drill_hole.diameter,
+                );
+                decks.push(FiredDeck {
+                    deck: (drill_hole.id, index),
+                    time: drill_hole.nominal_firing_time() + deck.delay,
+                    mass: deck.mass(drill_hole.diameter, product),
+                    position,
+                    confinement: airblast.confinement(
+                        drill_hole.diameter,
+                        stemming_above(drill_hole, deck),
+                        burden,
+                    ),
+                });
+            }
+        }
+        decks.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let mut report = BlastReport {
+            decks: decks.len(),
+            total_charge: decks.iter().map(|deck| deck.mass).sum(),
+            ..default()
+        };
+
+        // every deck opens a window so the heaviest delay is found wherever it starts,
+        // (start, end, charge) of each
+        let mut windows = Vec::with_capacity(decks.len());
+        let mut previous_end = 0;
+        for start in 0..decks.len() {
+            let first = decks[start].time;
+            let end = start
+                + decks[start..]
+                    .iter()
+                    .take_while(|deck| deck.time - first <= vibration.delay_window)
+                    .count();
+            let group = &decks[start..end];
+            let charge: f32 = group.iter().map(|deck| deck.mass).sum();
+            if charge > report.max_charge_per_delay {
+                report.max_charge_per_delay = charge;
+                report.max_charge_time = first;
+            }
+            // a window ending where the previous one did only holds decks already listed
+            if group.len() > 1 && end > previous_end {
+                report.overlaps.push(DelayGroup {
+                    time: first,
+                    span: group[group.len() - 1].time - first,
+                    decks: group.iter().map(|deck| deck.deck).collect(),
+                    charge,
+                    largest_deck: group.iter().map(|deck| deck.mass).fold(0.0, f32::max),
+                });
+            }
+            previous_end = end;
+            windows.push((start, end, charge));
+        }
+
+        report.within_limits = true;
+        for monitor in monitors.iter() {
+            let mut result = VibrationResult {
+                limit: monitor.ppv_limit,
+                ..default()
+            };
+            let mut blast = AirblastResult {
+                limit: monitor.airblast_limit,
+                ..default()
+            };
+            for &(start, end, charge) in windows.iter() {
+                let distance = decks[start..end]
+                    .iter()
+                    .map(|deck| deck.position.distance(monitor.position))
+                    .fold(f32::INFINITY, f32::min);
+                let ppv = vibration.ppv(distance, charge);
+                if ppv > result.ppv {
+                    result.ppv = ppv;
+                    result.time = decks[start].time;
+                    result.charge = charge;
+                    result.distance = distance;
+                    result.scaled_distance = vibration.scaled_distance(distance, charge);
+                }
+                // the whole delay's charge, but each deck vents from its own place and
+                // confinement, the worst of them is heard
+                for deck in decks[start..end].iter() {
+                    let distance = deck.position.distance(monitor.position);
+                    let pressure = airblast.overpressure(distance, charge, deck.confinement);
+                    if pressure > blast.pressure {
+                        blast.pressure = pressure;
+                        blast.overpressure = decibels(pressure);
+                        blast.time = decks[start].time;
+                        blast.charge = charge;
+                        blast.distance = distance;
+                        blast.scaled_distance = airblast.scaled_distance(distance, charge);
+                        blast.confinement = deck.confinement;
+                    }
+                }
+            }
+            result.exceeds_limit = monitor.ppv_limit.is_some_and(|limit| result.ppv > limit);
+            blast.exceeds_limit = monitor
+                .airblast_limit
+                .is_some_and(|limit| blast.overpressure > limit);
+            report.within_limits &= !(result.exceeds_limit || blast.exceeds_limit);
+            report.monitors.push(MonitorReport {
+                name: monitor.name.clone(),
+                position: monitor.position,
+                vibration: result,
+                airblast: blast,
+            });
+        }
+        report
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+// Redo the report whenever the holes, products, bench, monitors or site laws change
+pub fn blast_report_system(
+    drill_holes: Res<DrillHoles>,
+    library: Res<ExplosiveLibrary>,
+    scenario: Res<BlastScenario>,
+    mut report: ResMut<BlastReport>,
+) {
+    if !(drill_holes.is_changed() || library.is_changed() || scenario.is_changed()) {
+        return;
+    }
+    *report = BlastReport::build(
+        &drill_holes,
+        &library,
+        &scenario.bench_geometry(),
+        &scenario.bench_resolution,
+        &scenario.monitors,
+        &scenario.vibration,
+        &scenario.airblast,
+    );
+    for monitor in report.monitors.iter() {
+        if monitor.vibration.exceeds_limit {
+            warn!(
+                "{}: predicted PPV {:.1} mm/s is over its limit",
+                monitor.name, monitor.vibration.ppv
+            );
+        }
+        if monitor.airblast.exceeds_limit {
+            warn!(
+                "{}: predicted airblast {:.0} dBL is over its limit",
+                monitor.name, monitor.airblast.overpressure
+            );
+        }
+    }
+}
+
+// Mark every monitor, red when a prediction is over its limit
+pub fn draw_monitors(mut gizmos: Gizmos, report: Res<BlastReport>) {
+    for monitor in report.monitors.iter() {
+        let color = if monitor.vibration.exceeds_limit || monitor.airblast.exceeds_limit {
+            RED
+        } else {
+            LIME
+        };
+        gizmos.sphere(monitor.position, Quat::IDENTITY, 0.5, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explosives::ExplosiveCharge;
+    use crate::useful_structs::DrillHole;
+
+    // 9 m vertical holes of 100 mm, 6 m of ANFO under 3 m of stemming
+    const DECK_MASS: f32 = 37.699;
+
+    fn drill_holes(times: &[f32]) -> DrillHoles {
+        DrillHoles::new(
+            times
+                .iter()
+                .enumerate()
+                .map(|(i, &time)| {
+                    let mut drill_hole =
+                        DrillHole::new(Vec3::new(i as f32 * 3.0, 10.0, 5.0), 1.0, 9.0, time);
+                    drill_hole.diameter = 0.1;
+                    drill_hole.charge = ExplosiveCharge::single_deck("ANFO", 6.0, 3.0, 9.0);
+                    drill_hole
+                })
+                .collect(),
+        )
+    }
+
+    fn build(times: &[f32], monitors: &[Monitor]) -> BlastReport {
+        BlastReport::build(
+            &drill_holes(times),
+            &ExplosiveLibrary::default(),
+            &BenchGeometry::Box {
+                dimensions: [20.0, 10.0, 10.0],
+                position: [0.5, 0.5, 0.5],
+            },
+            &[1.0, 1.0, 1.0],
+            monitors,
+            &VibrationSettings::default(),
+            &AirblastSettings::default(),
+        )
+    }
+
+    #[test]
+    fn decks_within_the_window_are_one_delay() {
+        // 0 and 5 ms share a window, so do 5 and 10 ms, 30 ms fires on its own
+        let report = build(&[0.0, 5.0, 10.0, 30.0], &[]);
+        assert_eq!(report.decks, 4);
+        assert!((report.total_charge - 4.0 * DECK_MASS).abs() < 1e-2);
+        assert!((report.max_charge_per_delay - 2.0 * DECK_MASS).abs() < 1e-2);
+        assert_eq!(report.max_charge_time, 0.0);
+        assert_eq!(report.overlaps.len(), 2);
+        assert_eq!(report.overlaps[0].decks, vec![(1, 1), (2, 1)]);
+        assert_eq!(report.overlaps[0].span, 5.0);
+        assert_eq!(report.overlaps[1].time, 5.0);
+        assert!((report.overlaps[1].largest_deck - DECK_MASS).abs() < 1e-2);
+    }
+
+    #[test]
+    fn the_window_end_is_the_same_delay() {
+        let report = build(&[0.0, 8.0], &[]);
+        assert!((report.max_charge_per_delay - 2.0 * DECK_MASS).abs() < 1e-2);
+        assert_eq!(report.overlaps[0].span, 8.0);
+        // just past it is a separate delay
+        let report = build(&[0.0, 8.5], &[]);
+        assert!((report.max_charge_per_delay - DECK_MASS).abs() < 1e-2);
+        assert!(report.overlaps.is_empty());
+    }
+
+    #[test]
+    fn monitors_take_the_worst_delay() {
+        let monitor = Monitor {
+            name: "house".to_string(),
+            position: Vec3::new(0.0, 0.0, 105.0),
+            ppv_limit: Some(5.0),
+            airblast_limit: None,
+        };
+        let report = build(&[0.0, 5.0, 30.0], &[monitor]);
+        let vibration = &report.monitors[0].vibration;
+        assert!((vibration.charge - 2.0 * DECK_MASS).abs() < 1e-2);
+        assert_eq!(vibration.time, 0.0);
+        // the first hole's deck, its middle 6 m down the collar at 10 m
+        let distance = Vec3::new(0.0, 4.0, 5.0).distance(Vec3::new(0.0, 0.0, 105.0));
+        assert!((vibration.distance - distance).abs() < 1e-3);
+        let ppv = VibrationSettings::default().ppv(distance, 2.0 * DECK_MASS);
+        assert!((vibration.ppv - ppv).abs() < 1e-3);
+        assert!(vibration.exceeds_limit);
+        assert!(!report.within_limits);
+    }
+}
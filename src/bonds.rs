@@ -0,0 +1,241 @@
+use crate::blast_clock::BlastClock;
+use crate::joints::FaceProperties;
+use crate::simulation::physics_step_seconds;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BondSettings {
+    // strength of the cemented contact between neighbouring blocks
+    pub enabled: bool, // false spawns free standing blocks that only rest on each other
+    pub tensile_strength: f32, // N per m^2 of face pulling the blocks apart, at lithology::REFERENCE_UCS
+    pub shear_strength: f32, // N per m^2 of face sliding the blocks past each other, at lithology::REFERENCE_UCS
+}
+
+impl Default for BondSettings {
+    // scaled to the model impulses rather than real rock, for rock of the reference UCS a 0.5 m
+    // block bond holds the bench up under gravity but gives way close to a charge
+    fn default() -> Self {
+        BondSettings {
+            enabled: true,
+            tensile_strength: 300_000.0,
+            shear_strength: 500_000.0,
+        }
+    }
+}
+
+impl BondSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.tensile_strength > 0.0) {
+            return Err(format!(
+                "tensile_strength must be positive, got {}",
+                self.tensile_strength
+            ));
+        }
+        if !(self.shear_strength > 0.0) {
+            return Err(format!(
+                "shear_strength must be positive, got {}",
+                self.shear_strength
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A breakable bond between two neighbouring blocks, lives on a child entity of block `b` next to
+/// the fixed joint holding them together
+#[derive(Debug, Clone, Component)]
+pub struct Bond {
+    pub a: Entity,
+    pub b: Entity,
+    pub axis: Vec3,         // unit vector from a to b in a's frame
+    pub rest_length: f32,   // distance between the block centers when bonded
+    pub tensile_limit: f32, // N
+    pub shear_limit: f32,   // N
+}
+
+impl Bond {
+    /// Bond between `a` and `b` glued at their shared face, see `bond_blocks`
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        a: Entity,
+        b: Entity,
+        anchor_a: Vec3,
+        anchor_b: Vec3,
+        area: f32,
+        face: &FaceProperties,
+        strength_factor: f32,
+        settings: &BondSettings,
+    ) -> Self {
+        let offset = anchor_a - anchor_b;
+        Bond {
+            a,
+            b,
+            axis: offset.normalize(),
+            rest_length: offset.length(),
+            tensile_limit: settings.tensile_strength * area * face.strength * strength_factor,
+            shear_limit: settings.shear_strength * area * face.strength * strength_factor,
+        }
+    }
+
+    /// How the bond fails under `force` (N, in a's frame), None if it holds. The contact takes
+    /// compression, so the normal force only counts when the bond is `stretched`
+    pub fn failure(&self, force: Vec3, stretched: bool) -> Option<BondFailure> {
+        let normal = force.dot(self.axis);
+        let shear = (force - self.axis * normal).length();
+        if stretched && normal.abs() > self.tensile_limit {
+            Some(BondFailure::Tensile)
+        } else if shear > self.shear_limit {
+            Some(BondFailure::Shear)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BondFailure {
+    /// Pulled apart along the bond
+    Tensile,
+    /// Slid across the face
+    Shear,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BrokenBond {
+    pub position: Vec3, // middle of the face when the bond broke
+    pub failure: BondFailure,
+    pub time: f32, // blast clock time in milliseconds
+}
+
+/// Every bond that has snapped since the bench was built
+#[derive(Debug, Default, Resource, Serialize)]
+pub struct BrokenBonds(pub Vec<BrokenBond>);
+
+// Glue the blocks `a` and `b` at a point on their shared face, `anchor_a` and `anchor_b` go to it
+// from each block's center and `area` is the shared face area. Jointed faces keep only their share of the intact strength, `strength_factor` scales
+// the settings for the weaker of the two lithologies
+#[allow(clippy::too_many_arguments)]
+pub fn bond_blocks(
+    commands: &mut Commands,
+    a: Entity,
+    b: Entity,
+    anchor_a: Vec3,
+    anchor_b: Vec3,
+    area: f32,
+    face: &FaceProperties,
+    strength_factor: f32,
+    settings: &BondSettings,
+) {
+    let joint = FixedJointBuilder::new()
+        .local_anchor1(anchor_a)
+        .local_anchor2(anchor_b);
+    let bond = Bond::new(
+        a,
+        b,
+        anchor_a,
+        anchor_b,
+        area,
+        face,
+        strength_factor,
+        settings,
+    );
+    commands.entity(b).with_children(|children| {
+        children.spawn((
+            ImpulseJoint::new(a, joint),
+            bond,
+            TransformBundle::default(),
+        ));
+    });
+}
+
+// Snap every bond whose joint had to hold more than its strength last step. Removing the joint
+// entity lets bevy_rapier drop the joint, the blocks are then only held by contacts
+pub fn bond_breaking_system(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    time: Res<Time>,
+    timestep_mode: Res<TimestepMode>,
+    clock: Res<BlastClock>,
+    mut broken_bonds: ResMut<BrokenBonds>,
+    bonds: Query<(Entity, &Bond, &RapierImpulseJointHandle)>,
+    blocks: Query<&Transform>,
+) {
+    let dt = physics_step_seconds(&timestep_mode, &time);
+    if dt <= 0.0 {
+        return;
+    }
+    for (entity, bond, handle) in bonds.iter() {
+        let Some(joint) = rapier_context.impulse_joints.get(handle.0) else {
+            continue;
+        };
+        let Ok([transform_a, transform_b]) = blocks.get_many([bond.a, bond.b]) else {
+            continue;
+        };
+        // linear part of the joint impulse, in a's frame like the bond axis
+        let force = Vec3::new(joint.impulses[0], joint.impulses[1], joint.impulses[2]) / dt;
+        let stretched =
+            transform_a.translation.distance(transform_b.translation) > bond.rest_length;
+        let Some(failure) = bond.failure(force, stretched) else {
+            continue;
+        };
+        broken_bonds.0.push(BrokenBond {
+            position: (transform_a.translation + transform_b.translation) / 2.0,
+            failure,
+            time: clock.elapsed,
+        });
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two 0.5 m blocks side by side along x, bonded across a jointed face at half the reference UCS
+    fn bond() -> Bond {
+        let face = FaceProperties {
+            friction: 0.3,
+            strength: 0.1,
+        };
+        Bond::new(
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+            Vec3::new(0.25, 0.0, 0.0),
+            Vec3::new(-0.25, 0.0, 0.0),
+            0.25,
+            &face,
+            0.5,
+            &BondSettings::default(),
+        )
+    }
+
+    #[test]
+    fn limits_scale_with_face_area_and_strength() {
+        let bond = bond();
+        assert_eq!(bond.axis, Vec3::X);
+        assert_eq!(bond.rest_length, 0.5);
+        // 300 kPa and 500 kPa over 0.25 m², a tenth left by the joint, half by the rock
+        assert!((bond.tensile_limit - 3750.0).abs() < 1e-2);
+        assert!((bond.shear_limit - 6250.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn bonds_break_past_their_limits() {
+        let bond = bond();
+        assert_eq!(bond.failure(Vec3::X * 3000.0, true), None);
+        assert_eq!(
+            bond.failure(Vec3::X * 4000.0, true),
+            Some(BondFailure::Tensile)
+        );
+        // pushed together, the contact takes it
+        assert_eq!(bond.failure(Vec3::NEG_X * 4000.0, false), None);
+        assert_eq!(bond.failure(Vec3::Y * 6000.0, false), None);
+        assert_eq!(
+            bond.failure(Vec3::new(-4000.0, 7000.0, 0.0), false),
+            Some(BondFailure::Shear)
+        );
+    }
+}
@@ -1,22 +1,37 @@
+use crate::explosives::*;
+use crate::initiation::DetonationEvent;
 use crate::useful_structs::*;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
+#[allow(clippy::too_many_arguments)]
 pub fn drill_hole_go_boom_system(
     mut commands: Commands,
     mut rapier_context: ResMut<RapierContext>,
     drill_holes: Res<DrillHoles>,
-    time: Res<Time>,
-    force_magnitude: f32,
-    mut entity_query: Query<(&Transform, Option<&mut ExternalImpulse>)>,
+    explosive_library: Res<ExplosiveLibrary>,
+    mut detonations: EventReader<DetonationEvent>,
+    impulse_constant: f32,
+    attenuation: f32,
+    mut entity_query: Query<(&Transform, Option<&mut ExternalImpulse>, Option<&mut Block>)>,
 ) {
-    for drill_hole in drill_holes.0.iter() {
-        if time.elapsed_seconds() < drill_hole.timing + 1.0
-            && time.elapsed_seconds() > drill_hole.timing - 1.0
-        {
-            // only apply the force if the drill hole is active
+    // each charge deck pushes once, on the frame it detonates. Stemming and air decks hold no
+    // energy, blocks level with them only feel the decks above and below
+    for detonation in detonations.read() {
+        let Some(drill_hole) = drill_holes.get(detonation.hole_id) else {
+            continue;
+        };
+        if let Some(deck) = drill_hole.charge.intervals.get(detonation.deck) {
+            let Some(product) = explosive_library.get(&deck.product) else {
+                warn!(
+                    "Drill hole {} uses unknown explosive '{}', the deck did not fire",
+                    drill_hole.id, deck.product
+                );
+                continue;
+            };
+            let anfo_equivalent_mass = deck.anfo_equivalent_mass(drill_hole.diameter, product);
 
-            let half_height = drill_hole.height / 2.0; // Half the height of the cylindrical area
+            let half_height = drill_hole.drilled_length() / 2.0; // Half the height of the cylindrical area
 
             // Create a cylinder collider representing the area of effect
             let cylinder_collider = Collider::cylinder(half_height, drill_hole.radius);
@@ -24,8 +39,8 @@ pub fn drill_hole_go_boom_system(
             // Collect entities within the cylindrical area
             let mut entities_in_cylinder = Vec::new();
             rapier_context.intersections_with_shape(
-                drill_hole.position,
-                Quat::IDENTITY,
+                drill_hole.center(),
+                drill_hole.rotation(),
                 &cylinder_collider,
                 QueryFilter::default(),
                 |entity| {
@@ -36,10 +51,27 @@ pub fn drill_hole_go_boom_system(
 
             // Apply force to each entity within the cylinder
             for entity in entities_in_cylinder {
-                if let Ok((transform, external_impulse_option)) = entity_query.get_mut(entity) {
-                    // Calculate direction from the drill hole to the entity
-                    let direction = (transform.translation - drill_hole.position).normalize();
-                    let impulse_vector = direction * force_magnitude;
+                if let Ok((transform, external_impulse_option, block)) =
+                    entity_query.get_mut(entity)
+                {
+                    // Push away from the nearest point of the deck
+                    let charge_point =
+                        drill_hole.closest_interval_point(deck, transform.translation);
+                    let offset = transform.translation - charge_point;
+                    let direction = offset.try_normalize().unwrap_or(Vec3::Y);
+                    let impulse_vector = direction
+                        * blast_impulse(
+                            anfo_equivalent_mass,
+                            product.vod,
+                            offset.length(),
+                            drill_hole.diameter / 2.0,
+                            impulse_constant,
+                            attenuation,
+                        );
+
+                    if let Some(mut block) = block {
+                        block.record_impulse(drill_hole.id, impulse_vector.length());
+                    }
 
                     // Apply impulse
                     if let Some(mut external_impulse) = external_impulse_option {
@@ -0,0 +1,91 @@
+use crate::explosives::IntervalKind;
+use crate::scenario::BlastScenario;
+use crate::useful_structs::*;
+use bevy::color::palettes::css::{DEEP_SKY_BLUE, GRAY, ORANGE_RED, WHITE};
+use bevy::prelude::*;
+
+// Outline every drill hole's area of effect and draw its loading along the axis. Drawn from the
+// DrillHoles resource each frame, so holes added, moved or removed through the editor show up
+// immediately
+pub fn draw_drill_holes(mut gizmos: Gizmos, drill_holes: Res<DrillHoles>) {
+    for drill_hole in drill_holes.0.iter() {
+        gizmos.primitive_3d(
+            &Cylinder::new(drill_hole.radius, drill_hole.drilled_length()),
+            drill_hole.center(),
+            drill_hole.rotation(),
+            DEEP_SKY_BLUE,
+        );
+        for interval in drill_hole.charge.intervals.iter() {
+            let color = match interval.kind {
+                IntervalKind::Charge => ORANGE_RED,
+                IntervalKind::Stemming => GRAY,
+                IntervalKind::Air => WHITE,
+            };
+            gizmos.line(
+                drill_hole.point_at_depth(interval.from),
+                drill_hole.point_at_depth(interval.to),
+                color,
+            );
+        }
+    }
+}
+
+// marks the hole column meshes so they can be rebuilt when the holes change
+#[derive(Component)]
+pub struct ChargeColumn;
+
+// Spawn a mesh for every interval of every hole so charge placement can be seen through the
+// carved bench. Rebuilt whenever the DrillHoles resource changes, gaps in the loading are left
+// open
+pub fn update_charge_columns(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    scenario: Res<BlastScenario>,
+    drill_holes: Res<DrillHoles>,
+    columns: Query<Entity, With<ChargeColumn>>,
+) {
+    if !drill_holes.is_changed() && !scenario.is_changed() {
+        return;
+    }
+    for entity in columns.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !scenario.show_charge_columns {
+        return;
+    }
+
+    let charge_material = materials.add(StandardMaterial {
+        base_color: ORANGE_RED.into(),
+        ..default()
+    });
+    let stemming_material = materials.add(StandardMaterial {
+        base_color: GRAY.into(),
+        ..default()
+    });
+    let air_material = materials.add(StandardMaterial {
+        base_color: Color::srgba(1.0, 1.0, 1.0, 0.3),
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    });
+    for drill_hole in drill_holes.0.iter() {
+        for interval in drill_hole.charge.intervals.iter() {
+            let material = match interval.kind {
+                IntervalKind::Charge => charge_material.clone(),
+                IntervalKind::Stemming => stemming_material.clone(),
+                IntervalKind::Air => air_material.clone(),
+            };
+            let middle = drill_hole.point_at_depth((interval.from + interval.to) / 2.0);
+            commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(Cylinder::new(drill_hole.diameter / 2.0, interval.length())),
+                    material,
+                    transform: Transform::from_translation(middle)
+                        .with_rotation(drill_hole.rotation()),
+                    ..default()
+                },
+                ChargeColumn,
+            ));
+        }
+    }
+}
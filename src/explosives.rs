@@ -0,0 +1,435 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// VOD of bulk ANFO, products detonating faster than this deliver a sharper pulse
+pub const REFERENCE_VOD: f32 = 3800.0;
+/// Energy of ANFO in MJ/kg, relative weight strengths are measured against it
+pub const ANFO_ENERGY: f32 = 3.7;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExplosiveProduct {
+    // a named explosive product engineers can load into holes and decks
+    pub name: String,
+    pub density: f32, // kg/m^3
+    pub energy: f32,  // MJ/kg
+    pub vod: f32,     // velocity of detonation in m/s
+}
+
+impl ExplosiveProduct {
+    pub fn new(name: &str, density: f32, energy: f32, vod: f32) -> Self {
+        ExplosiveProduct {
+            name: name.to_string(),
+            density,
+            energy,
+            vod,
+        }
+    }
+
+    // energy per kg relative to ANFO, ANFO = 100
+    pub fn relative_weight_strength(&self) -> f32 {
+        self.energy / ANFO_ENERGY * 100.0
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("product name must not be empty".to_string());
+        }
+        if !(self.density > 0.0) {
+            return Err(format!(
+                "{}: density must be positive, got {}",
+                self.name, self.density
+            ));
+        }
+        if !(self.energy > 0.0) {
+            return Err(format!(
+                "{}: energy must be positive, got {}",
+                self.name, self.energy
+            ));
+        }
+        if !(self.vod > 0.0) {
+            return Err(format!(
+                "{}: vod must be positive, got {}",
+                self.name, self.vod
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Every explosive product known to the simulation, keyed by name
+#[derive(Debug, Clone, Resource)]
+pub struct ExplosiveLibrary(pub HashMap<String, ExplosiveProduct>);
+
+impl Default for ExplosiveLibrary {
+    // typical bulk products, good enough until site data is loaded
+    fn default() -> Self {
+        let mut library = ExplosiveLibrary(HashMap::new());
+        library.insert(ExplosiveProduct::new(
+            "ANFO",
+            800.0,
+            ANFO_ENERGY,
+            REFERENCE_VOD,
+        ));
+        library.insert(ExplosiveProduct::new("Emulsion", 1200.0, 3.2, 5500.0));
+        library.insert(ExplosiveProduct::new("Heavy ANFO", 1100.0, 3.5, 4500.0));
+        library
+    }
+}
+
+impl ExplosiveLibrary {
+    pub fn get(&self, name: &str) -> Option<&ExplosiveProduct> {
+        self.0.get(name)
+    }
+
+    /// Add a product, replacing any product with the same name
+    pub fn insert(&mut self, product: ExplosiveProduct) {
+        self.0.insert(product.name.clone(), product);
+    }
+
+    /// Parse and validate a JSON array of products
+    pub fn read_products(json: &str) -> Result<Vec<ExplosiveProduct>, String> {
+        let products: Vec<ExplosiveProduct> =
+            serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for product in products.iter() {
+            product.validate()?;
+        }
+        Ok(products)
+    }
+
+    /// Parse and validate a JSON file of products
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_products_path(path: &str) -> Result<Vec<ExplosiveProduct>, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::read_products(&json).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Add every product of a JSON array, nothing is added if any of them is invalid
+    pub fn load_json(&mut self, json: &str) -> Result<(), String> {
+        for product in Self::read_products(json)? {
+            self.insert(product);
+        }
+        Ok(())
+    }
+
+    // products sorted by name, for the UI dropdown
+    pub fn sorted(&self) -> Vec<&ExplosiveProduct> {
+        let mut products: Vec<&ExplosiveProduct> = self.0.values().collect();
+        products.sort_by(|a, b| a.name.cmp(&b.name));
+        products
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntervalKind {
+    /// Explosive deck with its own product and detonator
+    Charge,
+    /// Inert stemming, confines the decks around it
+    Stemming,
+    /// Air deck, left empty on purpose
+    Air,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoleInterval {
+    // one stretch of the hole, depths are measured along the hole from the collar
+    pub kind: IntervalKind,
+    pub from: f32, // depth in m where the interval starts
+    pub to: f32,   // depth in m where the interval ends
+    #[serde(default)]
+    pub product: String, // name of a product in the ExplosiveLibrary, charge decks only
+    #[serde(default)]
+    pub delay: f32, // ms added to the hole's firing time, charge decks only
+}
+
+impl HoleInterval {
+    pub fn charge(product: &str, from: f32, to: f32, delay: f32) -> Self {
+        HoleInterval {
+            kind: IntervalKind::Charge,
+            from,
+            to,
+            product: product.to_string(),
+            delay,
+        }
+    }
+
+    pub fn inert(kind: IntervalKind, from: f32, to: f32) -> Self {
+        HoleInterval {
+            kind,
+            from,
+            to,
+            product: String::new(),
+            delay: 0.0,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.to - self.from
+    }
+
+    // kg of explosive in the interval for a hole of the given diameter (m)
+    pub fn mass(&self, diameter: f32, product: &ExplosiveProduct) -> f32 {
+        product.density * PI * (diameter / 2.0).powi(2) * self.length()
+    }
+
+    // kg of ANFO releasing the same energy
+    pub fn anfo_equivalent_mass(&self, diameter: f32, product: &ExplosiveProduct) -> f32 {
+        self.mass(diameter, product) * product.relative_weight_strength() / 100.0
+    }
+}
+
+/// How a drill hole is loaded, a list of charge decks and inert intervals from collar to toe
+///
+/// Only the charge decks release energy, stretches not covered by any interval are treated as air.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ExplosiveCharge {
+    pub intervals: Vec<HoleInterval>,
+}
+
+impl ExplosiveCharge {
+    // single deck at the bottom of the hole with stemming on top
+    pub fn single_deck(
+        product: &str,
+        charge_length: f32,
+        stemming_length: f32,
+        hole_length: f32,
+    ) -> Self {
+        let mut intervals = vec![HoleInterval::inert(
+            IntervalKind::Stemming,
+            0.0,
+            stemming_length,
+        )];
+        if hole_length - charge_length > stemming_length {
+            intervals.push(HoleInterval::inert(
+                IntervalKind::Air,
+                stemming_length,
+                hole_length - charge_length,
+            ));
+        }
+        intervals.push(HoleInterval::charge(
+            product,
+            hole_length - charge_length,
+            hole_length,
+            0.0,
+        ));
+        ExplosiveCharge { intervals }
+    }
+
+    // charge decks with their index in `intervals`, the index identifies the deck's detonator
+    pub fn decks(&self) -> impl Iterator<Item = (usize, &HoleInterval)> {
+        self.intervals
+            .iter()
+            .enumerate()
+            .filter(|(_, interval)| interval.kind == IntervalKind::Charge)
+    }
+
+    pub fn validate(&self, hole_length: f32) -> Result<(), String> {
+        let mut sorted: Vec<&HoleInterval> = self.intervals.iter().collect();
+        sorted.sort_by(|a, b| a.from.total_cmp(&b.from));
+        for (i, interval) in sorted.iter().enumerate() {
+            if !(interval.from >= 0.0 && interval.to > interval.from) {
+                return Err(format!(
+                    "interval {}-{} m must start at or below the collar and have a positive length",
+                    interval.from, interval.to
+                ));
+            }
+            if interval.to > hole_length + 1e-3 {
+                return Err(format!(
+                    "interval {}-{} m goes past the end of the {} m hole",
+                    interval.from, interval.to, hole_length
+                ));
+            }
+            if i > 0 && interval.from < sorted[i - 1].to - 1e-3 {
+                return Err(format!(
+                    "interval {}-{} m overlaps interval {}-{} m",
+                    interval.from,
+                    interval.to,
+                    sorted[i - 1].from,
+                    sorted[i - 1].to
+                ));
+            }
+            if interval.kind == IntervalKind::Charge {
+                if interval.product.is_empty() {
+                    return Err(format!(
+                        "charge deck {}-{} m has no product",
+                        interval.from, interval.to
+                    ));
+                }
+                if !(interval.delay >= 0.0) {
+                    return Err(format!(
+                        "charge deck {}-{} m delay must not be negative, got {}",
+                        interval.from, interval.to, interval.delay
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Impulse delivered to a block `distance` m from a charge, using cube-root scaled distance
+///
+/// `impulse_constant` and `attenuation` are the site constants K and beta in
+/// `I = K * W^(1/3) * (R / W^(1/3))^-beta`, where W is the ANFO equivalent charge mass.
+/// `min_distance` stops the impulse blowing up for blocks touching the charge.
+pub fn blast_impulse(
+    anfo_equivalent_mass: f32,
+    vod: f32,
+    distance: f32,
+    min_distance: f32,
+    impulse_constant: f32,
+    attenuation: f32,
+) -> f32 {
+    if anfo_equivalent_mass <= 0.0 {
+        return 0.0;
+    }
+    let cube_root_mass = anfo_equivalent_mass.cbrt();
+    let scaled_distance = distance.max(min_distance) / cube_root_mass;
+    let shock_factor = (vod / REFERENCE_VOD).sqrt();
+    impulse_constant * cube_root_mass * scaled_distance.powf(-attenuation) * shock_factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impulse_follows_scaled_distance() {
+        // 8 kg, so R / W^(1/3) is 2 at 4 m, and K W^(1/3) SD^-2 = 10 * 2 / 4
+        assert!((blast_impulse(8.0, REFERENCE_VOD, 4.0, 0.5, 10.0, 2.0) - 5.0).abs() < 1e-4);
+        // four times the VOD doubles it
+        let fast = blast_impulse(8.0, 4.0 * REFERENCE_VOD, 4.0, 0.5, 10.0, 2.0);
+        assert!((fast - 10.0).abs() < 1e-4);
+        // closer than the minimum distance counts as the minimum, SD 0.25
+        let touching = blast_impulse(8.0, REFERENCE_VOD, 0.1, 0.5, 10.0, 2.0);
+        assert!((touching - 320.0).abs() < 1e-2);
+        assert_eq!(blast_impulse(0.0, REFERENCE_VOD, 4.0, 0.5, 10.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn single_deck_fills_the_gap_with_air() {
+        let charge = ExplosiveCharge::single_deck("ANFO", 6.0, 3.0, 10.0);
+        assert_eq!(
+            charge.intervals,
+            [
+                HoleInterval::inert(IntervalKind::Stemming, 0.0, 3.0),
+                HoleInterval::inert(IntervalKind::Air, 3.0, 4.0),
+                HoleInterval::charge("ANFO", 4.0, 10.0, 0.0),
+            ]
+        );
+        assert!(charge.validate(10.0).is_ok());
+
+        // charged right up to the stemming
+        let charge = ExplosiveCharge::single_deck("ANFO", 7.0, 3.0, 10.0);
+        assert_eq!(charge.intervals.len(), 2);
+        assert_eq!(
+            charge.intervals[1],
+            HoleInterval::charge("ANFO", 3.0, 10.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn invalid_layouts_are_rejected() {
+        let validate = |intervals: Vec<HoleInterval>| ExplosiveCharge { intervals }.validate(10.0);
+        let stemming = |from, to| HoleInterval::inert(IntervalKind::Stemming, from, to);
+        let anfo = |from, to| HoleInterval::charge("ANFO", from, to, 0.0);
+
+        // listed out of order is fine, as is rounding at the toe
+        assert!(validate(vec![anfo(4.0, 10.0005), stemming(0.0, 4.0)]).is_ok());
+        // overlapping intervals
+        assert!(validate(vec![stemming(0.0, 3.0), anfo(2.5, 10.0)]).is_err());
+        // outside the hole
+        assert!(validate(vec![stemming(-1.0, 3.0), anfo(3.0, 10.0)]).is_err());
+        assert!(validate(vec![stemming(0.0, 3.0), anfo(3.0, 10.5)]).is_err());
+        // empty or reversed
+        assert!(validate(vec![stemming(3.0, 3.0)]).is_err());
+        assert!(validate(vec![anfo(10.0, 4.0)]).is_err());
+        // charge decks need a product and can't fire early
+        assert!(validate(vec![HoleInterval::charge("", 4.0, 10.0, 0.0)]).is_err());
+        assert!(validate(vec![HoleInterval::charge("ANFO", 4.0, 10.0, -5.0)]).is_err());
+    }
+
+    #[test]
+    fn library_presets() {
+        let library = ExplosiveLibrary::default();
+        let names: Vec<&str> = library
+            .sorted()
+            .iter()
+            .map(|product| product.name.as_str())
+            .collect();
+        assert_eq!(names, ["ANFO", "Emulsion", "Heavy ANFO"]);
+        for product in library.sorted() {
+            assert!(product.validate().is_ok());
+        }
+        assert_eq!(
+            library.get("ANFO").unwrap().relative_weight_strength(),
+            100.0
+        );
+        // 3.2 MJ/kg against ANFO's 3.7
+        let emulsion = library.get("Emulsion").unwrap();
+        assert!((emulsion.relative_weight_strength() - 86.486).abs() < 1e-2);
+    }
+
+    #[test]
+    fn loaded_products_replace_presets() {
+        let mut library = ExplosiveLibrary::default();
+        library
+            .load_json(
+                r#"[{"name": "ANFO", "density": 850, "energy": 3.7, "vod": 4000},
+                    {"name": "Site Emulsion", "density": 1250, "energy": 3.0, "vod": 5800}]"#,
+            )
+            .unwrap();
+        assert_eq!(library.0.len(), 4);
+        assert_eq!(library.get("ANFO").unwrap().density, 850.0);
+        assert_eq!(library.get("Site Emulsion").unwrap().vod, 5800.0);
+
+        // one bad product and none are added
+        let invalid = r#"[{"name": "Good", "density": 900, "energy": 3.5, "vod": 4200},
+                          {"name": "Bad", "density": 0, "energy": 3.5, "vod": 4200}]"#;
+        assert!(library.load_json(invalid).is_err());
+        assert!(library.get("Good").is_none());
+        assert!(library.load_json("not json").is_err());
+        assert!(ExplosiveProduct::new("", 800.0, 3.7, 3800.0)
+            .validate()
+            .is_err());
+    }
+
+    // two decks with stemming between them, the lower one on a 25 ms downhole delay
+    fn decked() -> ExplosiveCharge {
+        ExplosiveCharge {
+            intervals: vec![
+                HoleInterval::inert(IntervalKind::Stemming, 0.0, 2.0),
+                HoleInterval::charge("Emulsion", 2.0, 4.0, 0.0),
+                HoleInterval::inert(IntervalKind::Stemming, 4.0, 5.0),
+                HoleInterval::charge("ANFO", 5.0, 10.0, 25.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn only_charge_decks_fire() {
+        let charge = decked();
+        assert!(charge.validate(10.0).is_ok());
+        let decks: Vec<(usize, &str, f32)> = charge
+            .decks()
+            .map(|(index, deck)| (index, deck.product.as_str(), deck.delay))
+            .collect();
+        assert_eq!(decks, [(1, "Emulsion", 0.0), (3, "ANFO", 25.0)]);
+    }
+
+    #[test]
+    fn deck_masses() {
+        let library = ExplosiveLibrary::default();
+        let charge = decked();
+        // 5 m of ANFO at 800 kg/m³ in a 0.1 m hole is 10π kg
+        let anfo = library.get("ANFO").unwrap();
+        assert!((charge.intervals[3].mass(0.1, anfo) - 10.0 * PI).abs() < 1e-3);
+        assert!((charge.intervals[3].anfo_equivalent_mass(0.1, anfo) - 10.0 * PI).abs() < 1e-3);
+        // 2 m of emulsion is 6π kg, worth 3.2 / 3.7 as much ANFO
+        let emulsion = library.get("Emulsion").unwrap();
+        let equivalent = charge.intervals[1].anfo_equivalent_mass(0.1, emulsion);
+        assert!((equivalent - 6.0 * PI * 3.2 / 3.7).abs() < 1e-3);
+    }
+}
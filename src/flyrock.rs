@@ -0,0 +1,441 @@
+use crate::blast_clock::*;
+use crate::fragmentation::{FragmentationReport, IN_SITU_MOVEMENT, SETTLED_SPEED};
+use crate::scenario::BlastScenario;
+use crate::useful_structs::*;
+use bevy::color::palettes::css::{ORANGE, RED};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Most points kept in one trail, 10 s of flight at the default interval, well past any real one
+const MAX_TRAIL_POINTS: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlyrockSettings {
+    pub speed_threshold: f32, // m/s, blocks launched faster than this are flyrock
+    pub exclusion_radius: f32, // m from `center`, blocks landing or passing beyond it are flyrock
+    pub center: Option<Vec3>, // middle of the exclusion zone in plan, the middle of the collars if None
+    pub trail_interval: f32,  // ms between the points of a trail
+}
+
+impl Default for FlyrockSettings {
+    fn default() -> Self {
+        FlyrockSettings {
+            speed_threshold: 20.0,
+            exclusion_radius: 30.0,
+            center: None,
+            trail_interval: 20.0,
+        }
+    }
+}
+
+impl FlyrockSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.speed_threshold > 0.0) {
+            return Err(format!(
+                "speed_threshold must be positive, got {}",
+                self.speed_threshold
+            ));
+        }
+        if !(self.exclusion_radius > 0.0) {
+            return Err(format!(
+                "exclusion_radius must be positive, got {}",
+                self.exclusion_radius
+            ));
+        }
+        if !self.center.map_or(true, |center| center.is_finite()) {
+            return Err("center must be finite".to_string());
+        }
+        if !(self.trail_interval > 0.0) {
+            return Err(format!(
+                "trail_interval must be positive, got {}",
+                self.trail_interval
+            ));
+        }
+        Ok(())
+    }
+
+    // middle of the exclusion zone at the floor
+    pub fn zone_center(&self, drill_holes: &DrillHoles) -> Vec3 {
+        if let Some(center) = self.center {
+            return center;
+        }
+        if drill_holes.0.is_empty() {
+            return Vec3::ZERO;
+        }
+        let collars: Vec3 = drill_holes
+            .0
+            .iter()
+            .map(|drill_hole| drill_hole.collar)
+            .sum();
+        let center = collars / drill_holes.0.len() as f32;
+        Vec3::new(center.x, 0.0, center.z)
+    }
+
+    /// Why a block with this peak speed and furthest plan distance is flyrock, None if it isn't
+    pub fn reason(&self, peak_speed: f32, max_distance: f32) -> Option<FlyrockReason> {
+        let fast = peak_speed > self.speed_threshold;
+        let far = max_distance > self.exclusion_radius;
+        match (fast, far) {
+            (true, true) => Some(FlyrockReason::SpeedAndDistance),
+            (true, false) => Some(FlyrockReason::Speed),
+            (false, true) => Some(FlyrockReason::Distance),
+            (false, false) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FlyrockReason {
+    /// Launched faster than the speed threshold
+    Speed,
+    /// Went beyond the exclusion radius
+    Distance,
+    /// Both
+    SpeedAndDistance,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FlyrockFragment {
+    pub entity: Entity, // the block's entity, unlike `origin` it is unique
+    pub origin: UVec3,  // voxel index of the block, see Block
+    pub original_position: Vec3,
+    pub position: Vec3,    // where it is now
+    pub peak_speed: f32,   // m/s
+    pub max_distance: f32, // furthest from the zone center in plan, m
+    pub displaced_by: Option<u32>,
+    pub reason: FlyrockReason,
+    pub trail: Vec<Vec3>, // positions every trail_interval ms while it moves, since it started moving fast
+}
+
+/// Every block flagged as flyrock so far in the current blast
+#[derive(Debug, Clone, Default, Resource, Serialize)]
+pub struct FlyrockReport {
+    pub speed_threshold: f32,
+    pub exclusion_radius: f32,
+    pub center: Vec3,
+    pub max_speed: f32,    // fastest block of the blast, flagged or not, m/s
+    pub max_distance: f32, // furthest any tracked block got from the center, m
+    pub fragments: Vec<FlyrockFragment>,
+}
+
+impl FlyrockReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Flight {
+    trail: Vec<Vec3>, // until the block is flagged, then the trail moves to its fragment
+    max_distance: f32,
+    fragment: Option<usize>, // index in FlyrockReport::fragments once flagged
+}
+
+/// Trails of the blocks fast or far enough to possibly become flyrock, keeping a trail for every
+/// block of the bench would cost far too much
+#[derive(Debug, Default, Resource)]
+pub struct FlyrockTracker {
+    flights: HashMap<Entity, Flight>,
+    last_sample: Option<f32>, // blast clock time of the last trail point
+}
+
+// a block at rest adds nothing to its trail, and a trail stops growing at its limit
+fn record_trail(sample: bool, speed: f32, trail: &[Vec3]) -> bool {
+    sample && speed > SETTLED_SPEED && trail.len() < MAX_TRAIL_POINTS
+}
+
+impl FlyrockTracker {
+    /// Follow one block now at `position` moving at `speed` m/s, adding it to the report once it
+    /// crosses either limit. `sample` is whether this step adds a trail point
+    #[allow(clippy::too_many_arguments)]
+    pub fn track(
+        &mut self,
+        report: &mut FlyrockReport,
+        settings: &FlyrockSettings,
+        center: Vec3,
+        sample: bool,
+        entity: Entity,
+        block: &mut Block,
+        position: Vec3,
+        speed: f32,
+    ) {
+        if speed > block.peak_speed {
+            block.peak_speed = speed;
+        }
+        report.max_speed = report.max_speed.max(block.peak_speed);
+        // blocks the blast left in place can sit outside the zone on a wide bench, only distance
+        // travelled counts
+        let distance = if block.movement(position).length() >= IN_SITU_MOVEMENT {
+            (position - center).xz().length()
+        } else {
+            0.0
+        };
+
+        let close_call = block.peak_speed > settings.speed_threshold / 2.0
+            || distance > settings.exclusion_radius / 2.0;
+        if !close_call && !self.flights.contains_key(&entity) {
+            return;
+        }
+        let flight = self.flights.entry(entity).or_insert_with(|| Flight {
+            trail: vec![block.original_position],
+            ..default()
+        });
+        flight.max_distance = flight.max_distance.max(distance);
+        report.max_distance = report.max_distance.max(flight.max_distance);
+
+        let Some(reason) = settings.reason(block.peak_speed, flight.max_distance) else {
+            if record_trail(sample, speed, &flight.trail) {
+                flight.trail.push(position);
+            }
+            return;
+        };
+        // listed once, then updated in place
+        let index = *flight.fragment.get_or_insert_with(|| {
+            report.fragments.push(FlyrockFragment {
+                entity,
+                origin: block.origin,
+                original_position: block.original_position,
+                position,
+                peak_speed: block.peak_speed,
+                max_distance: flight.max_distance,
+                displaced_by: block.displaced_by,
+                reason,
+                trail: std::mem::take(&mut flight.trail),
+            });
+            report.fragments.len() - 1
+        });
+        let fragment = &mut report.fragments[index];
+        fragment.position = position;
+        fragment.peak_speed = block.peak_speed;
+        fragment.max_distance = flight.max_distance;
+        fragment.displaced_by = block.displaced_by;
+        fragment.reason = reason;
+        if record_trail(sample, speed, &fragment.trail) {
+            fragment.trail.push(position);
+        }
+    }
+}
+
+// Follow the blocks while the blast runs and until the muck settles: keep every block's peak speed,
+// trail the ones that come within half the speed threshold or exclusion radius, and list those that
+// cross either limit
+#[allow(clippy::too_many_arguments)]
+pub fn flyrock_system(
+    clock: Res<BlastClock>,
+    scenario: Res<BlastScenario>,
+    drill_holes: Res<DrillHoles>,
+    rapier_context: Res<RapierContext>,
+    fragmentation: Res<FragmentationReport>,
+    mut tracker: ResMut<FlyrockTracker>,
+    mut report: ResMut<FlyrockReport>,
+    mut blocks: Query<(Entity, &mut Block, &Transform, &RapierRigidBodyHandle)>,
+) {
+    if clock.state == BlastState::Armed {
+        if tracker.last_sample.is_some() {
+            *tracker = FlyrockTracker::default();
+            *report = FlyrockReport::default();
+        }
+        return;
+    }
+    // the clock keeps running after the muck has settled, nothing moves any more
+    if clock.state != BlastState::Firing || fragmentation.time.is_some() {
+        return;
+    }
+    let settings = &scenario.flyrock;
+    let sample = tracker
+        .last_sample
+        .map_or(true, |last| clock.elapsed - last >= settings.trail_interval);
+    if sample {
+        tracker.last_sample = Some(clock.elapsed);
+    }
+    let center = settings.zone_center(&drill_holes);
+    report.speed_threshold = settings.speed_threshold;
+    report.exclusion_radius = settings.exclusion_radius;
+    report.center = center;
+
+    let flagged = report.fragments.len();
+    for (entity, mut block, transform, handle) in blocks.iter_mut() {
+        let Some(body) = rapier_context.bodies.get(handle.0) else {
+            continue;
+        };
+        let speed = body.linvel().norm();
+        tracker.track(
+            &mut report,
+            settings,
+            center,
+            sample,
+            entity,
+            &mut block,
+            transform.translation,
+            speed,
+        );
+    }
+
+    if report.fragments.len() > flagged {
+        warn!(
+            "{} flyrock fragments, the furthest {:.1} m from the blast",
+            report.fragments.len(),
+            report.max_distance
+        );
+    }
+}
+
+// Trail every flyrock fragment in red and outline the exclusion zone
+pub fn draw_flyrock(
+    mut gizmos: Gizmos,
+    scenario: Res<BlastScenario>,
+    drill_holes: Res<DrillHoles>,
+    report: Res<FlyrockReport>,
+) {
+    gizmos.circle(
+        scenario.flyrock.zone_center(&drill_holes) + Vec3::Y * 0.1,
+        Dir3::Y,
+        scenario.flyrock.exclusion_radius,
+        ORANGE,
+    );
+    for fragment in report.fragments.iter() {
+        gizmos.linestrip(
+            fragment
+                .trail
+                .iter()
+                .copied()
+                .chain(std::iter::once(fragment.position)),
+            RED,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(x: f32) -> Block {
+        Block {
+            origin: UVec3::ZERO,
+            original_position: Vec3::new(x, 1.0, 0.0),
+            size: Vec3::ONE,
+            mass: 1.0,
+            lithology: 0,
+            grade: 0.0,
+            displaced_by: None,
+            max_impulse: 0.0,
+            peak_speed: 0.0,
+        }
+    }
+
+    fn settings() -> FlyrockSettings {
+        FlyrockSettings {
+            center: Some(Vec3::ZERO),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn reason_by_limit() {
+        // 20 m/s and 30 m by default
+        let settings = settings();
+        assert_eq!(settings.reason(10.0, 10.0), None);
+        assert_eq!(settings.reason(20.0, 30.0), None);
+        assert_eq!(settings.reason(25.0, 10.0), Some(FlyrockReason::Speed));
+        assert_eq!(settings.reason(10.0, 35.0), Some(FlyrockReason::Distance));
+        assert_eq!(
+            settings.reason(25.0, 35.0),
+            Some(FlyrockReason::SpeedAndDistance)
+        );
+    }
+
+    #[test]
+    fn thrown_blocks_are_listed_once() {
+        let settings = settings();
+        let mut tracker = FlyrockTracker::default();
+        let mut report = FlyrockReport::default();
+        let entity = Entity::from_raw(1);
+        let mut block = block(5.0);
+
+        // close enough to trail, not yet flyrock
+        let mut track = |block: &mut Block, x: f32, speed: f32| {
+            let position = Vec3::new(x, 1.0, 0.0);
+            tracker.track(
+                &mut report,
+                &settings,
+                Vec3::ZERO,
+                true,
+                entity,
+                block,
+                position,
+                speed,
+            );
+        };
+        track(&mut block, 6.0, 12.0);
+        track(&mut block, 20.0, 15.0);
+        track(&mut block, 40.0, 5.0);
+        track(&mut block, 41.0, 25.0);
+        assert_eq!(report.fragments.len(), 1);
+        let fragment = &report.fragments[0];
+        assert_eq!(fragment.entity, entity);
+        assert_eq!(fragment.reason, FlyrockReason::SpeedAndDistance);
+        assert_eq!(fragment.max_distance, 41.0);
+        assert_eq!(fragment.peak_speed, 25.0);
+        assert_eq!(block.peak_speed, 25.0);
+        // the trail from before it was flagged is kept
+        assert_eq!(
+            fragment.trail,
+            [5.0, 6.0, 20.0, 40.0, 41.0].map(|x| Vec3::new(x, 1.0, 0.0))
+        );
+        assert_eq!(report.max_speed, 25.0);
+        assert_eq!(report.max_distance, 41.0);
+    }
+
+    #[test]
+    fn blocks_left_in_place_are_not_flyrock() {
+        // well outside the exclusion zone, but barely nudged
+        let settings = settings();
+        let mut tracker = FlyrockTracker::default();
+        let mut report = FlyrockReport::default();
+        let mut block = block(50.0);
+        let position = Vec3::new(50.05, 1.0, 0.0);
+        tracker.track(
+            &mut report,
+            &settings,
+            Vec3::ZERO,
+            true,
+            Entity::from_raw(1),
+            &mut block,
+            position,
+            1.0,
+        );
+        assert!(report.fragments.is_empty());
+        assert!(tracker.flights.is_empty());
+        assert_eq!(report.max_distance, 0.0);
+    }
+
+    #[test]
+    fn trails_stop_at_the_limit() {
+        let settings = settings();
+        let mut tracker = FlyrockTracker::default();
+        let mut report = FlyrockReport::default();
+        let mut block = block(0.0);
+        for i in 0..MAX_TRAIL_POINTS + 10 {
+            tracker.track(
+                &mut report,
+                &settings,
+                Vec3::ZERO,
+                true,
+                Entity::from_raw(1),
+                &mut block,
+                Vec3::new(0.0, 1.0 + i as f32, 0.0),
+                30.0,
+            );
+        }
+        assert_eq!(report.fragments[0].reason, FlyrockReason::Speed);
+        assert_eq!(report.fragments[0].trail.len(), MAX_TRAIL_POINTS);
+
+        // nothing is added between samples or once the block has settled
+        assert!(!record_trail(false, 30.0, &[]));
+        assert!(!record_trail(true, SETTLED_SPEED / 2.0, &[]));
+        assert!(record_trail(true, 30.0, &[]));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::blast_clock::*;
+use crate::bonds::Bond;
+use crate::initiation::InitiationPlan;
+use crate::joints::RockMass;
+use crate::kuz_ram::*;
+use crate::useful_structs::Block;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_rapier3d::prelude::*;
+use serde::Serialize;
+
+/// Blocks slower than this (m/s) count as settled
+pub const SETTLED_SPEED: f32 = 0.05;
+/// Fragments whose blocks all moved less than this (m) are still part of the bench, not muck
+pub const IN_SITU_MOVEMENT: f32 = 0.1;
+/// Milliseconds after the last deck before the muck is checked, the blocks only pick up speed a
+/// step after their impulse
+pub const MIN_SETTLE_TIME: f32 = 500.0;
+/// Number of sieve sizes on the cumulative curve
+const CURVE_POINTS: usize = 30;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SievePoint {
+    pub size: f32,            // sieve size in m
+    pub passing: f32,         // % of the muck mass in smaller fragments
+    pub kuz_ram: Option<f32>, // % passing predicted by Kuz-Ram at the same size
+    pub swebrec: Option<f32>, // and by Swebrec
+}
+
+/// Size distribution of the muck once it has settled, a fragment is a group of blocks still held
+/// together by bonds and its size is the side of a cube of the same volume
+#[derive(Debug, Clone, Default, Resource, Serialize)]
+pub struct FragmentationReport {
+    pub time: Option<f32>, // blast clock time in ms the muck settled at, None until it has
+    pub fragments: usize,
+    pub blocks: usize,         // blocks in the muck
+    pub in_situ_blocks: usize, // blocks that stayed in the bench
+    pub mass: f32,             // kg of muck
+    pub p20: f32,
+    pub p50: f32,
+    pub p80: f32,
+    pub top_size: f32,
+    pub curve: Vec<SievePoint>,
+    pub predicted: Option<KuzRam>, // the empirical prediction to compare against
+}
+
+impl FragmentationReport {
+    /// Group the blocks by the bonds left between them and sieve the groups that moved, next to
+    /// the `prediction` if there is one
+    pub fn build<'a>(
+        blocks: impl Iterator<Item = (Entity, &'a Block, Vec3)>,
+        bonds: impl Iterator<Item = &'a Bond>,
+        rock_mass: &RockMass,
+        prediction: Option<&KuzRam>,
+    ) -> Self {
+        // union find over the blocks, joined by every intact bond
+        let mut index: HashMap<Entity, usize> = HashMap::new();
+        let mut block_data: Vec<(f32, f32, bool)> = Vec::new(); // (mass, volume, moved)
+        for (entity, block, translation) in blocks {
+            let density = rock_mass.lithologies[block.lithology].density;
+            index.insert(entity, block_data.len());
+            block_data.push((
+                block.mass,
+                block.mass / density,
+                block.movement(translation).length() >= IN_SITU_MOVEMENT,
+            ));
+        }
+        let mut parent: Vec<usize> = (0..block_data.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for bond in bonds {
+            let (Some(&a), Some(&b)) = (index.get(&bond.a), index.get(&bond.b)) else {
+                continue;
+            };
+            let (root_a, root_b) = (root(&mut parent, a), root(&mut parent, b));
+            parent[root_a] = root_b;
+        }
+
+        // (mass, volume, moved, blocks) of every fragment
+        let mut groups: HashMap<usize, (f32, f32, bool, usize)> = HashMap::new();
+        for (i, (mass, volume, moved)) in block_data.iter().enumerate() {
+            let group = groups
+                .entry(root(&mut parent, i))
+                .or_insert((0.0, 0.0, false, 0));
+            group.0 += mass;
+            group.1 += volume;
+            group.2 |= moved;
+            group.3 += 1;
+        }
+
+        let mut report = FragmentationReport {
+            predicted: prediction.cloned(),
+            ..default()
+        };
+        // (size, mass) of every fragment in the muck, smallest first
+        let mut fragments: Vec<(f32, f32)> = Vec::new();
+        for (mass, volume, moved, blocks) in groups.into_values() {
+            if moved {
+                fragments.push((volume.cbrt(), mass));
+                report.blocks += blocks;
+                report.mass += mass;
+            } else {
+                report.in_situ_blocks += blocks;
+            }
+        }
+        fragments.sort_by(|a, b| a.0.total_cmp(&b.0));
+        report.fragments = fragments.len();
+        let (Some(&(smallest, _)), Some(&(top_size, _))) = (fragments.first(), fragments.last())
+        else {
+            return report;
+        };
+
+        // cumulative % passing after every fragment
+        let mut cumulative = Vec::with_capacity(fragments.len());
+        let mut passing_mass = 0.0;
+        for (size, mass) in fragments.iter() {
+            passing_mass += mass;
+            cumulative.push((*size, 100.0 * passing_mass / report.mass));
+        }
+        report.p20 = size_passing(&cumulative, 20.0);
+        report.p50 = size_passing(&cumulative, 50.0);
+        report.p80 = size_passing(&cumulative, 80.0);
+        report.top_size = top_size;
+
+        // sieve sizes spread evenly on a log scale like a grading chart
+        for i in 0..CURVE_POINTS {
+            let size = if top_size > smallest {
+                smallest * (top_size / smallest).powf(i as f32 / (CURVE_POINTS - 1) as f32)
+            } else {
+                top_size
+            };
+            let passing = cumulative
+                .iter()
+                .take_while(|(fragment_size, _)| *fragment_size <= size)
+                .last()
+                .map_or(0.0, |(_, passing)| *passing);
+            report.curve.push(SievePoint {
+                size,
+                passing,
+                kuz_ram: prediction.map(|prediction| prediction.kuz_ram_passing(size)),
+                swebrec: prediction.map(|prediction| prediction.swebrec_passing(size)),
+            });
+        }
+        report
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+// Size at `percent` passing, interpolated between the fragments either side of it
+fn size_passing(cumulative: &[(f32, f32)], percent: f32) -> f32 {
+    let mut previous = (0.0, 0.0);
+    for &(size, passing) in cumulative {
+        if passing >= percent {
+            let t = (percent - previous.1) / (passing - previous.1);
+            return previous.0 + (size - previous.0) * t;
+        }
+        previous = (size, passing);
+    }
+    previous.0
+}
+
+// Sieve the muck once every deck has fired and the blocks have come to rest, and forget the report
+// when the bench is reset
+#[allow(clippy::too_many_arguments)]
+pub fn fragmentation_system(
+    clock: Res<BlastClock>,
+    plan: Res<InitiationPlan>,
+    rapier_context: Res<RapierContext>,
+    rock_mass: Res<RockMass>,
+    prediction: Res<FragmentationPrediction>,
+    mut report: ResMut<FragmentationReport>,
+    blocks: Query<(Entity, &Block, &Transform, &RapierRigidBodyHandle)>,
+    bonds: Query<&Bond>,
+) {
+    if clock.state == BlastState::Armed {
+        if report.time.is_some() {
+            *report = FragmentationReport::default();
+        }
+        return;
+    }
+    if report.time.is_some()
+        || clock.state != BlastState::Firing
+        || plan.detonated.len() < plan.firing_times.len()
+    {
+        return;
+    }
+    let last_firing_time = plan.firing_times.values().fold(0.0, |a: f32, b| a.max(*b));
+    if clock.elapsed < last_firing_time + MIN_SETTLE_TIME {
+        return;
+    }
+    let moving = blocks.iter().any(|(_, _, _, handle)| {
+        rapier_context
+            .bodies
+            .get(handle.0)
+            .is_some_and(|body| body.linvel().norm() > SETTLED_SPEED)
+    });
+    if moving {
+        return;
+    }
+
+    *report = FragmentationReport::build(
+        blocks
+            .iter()
+            .map(|(entity, block, transform, _)| (entity, block, transform.translation)),
+        bonds.iter(),
+        &rock_mass,
+        prediction.0.as_ref(),
+    );
+    report.time = Some(clock.elapsed);
+    info!(
+        "Muck settled at {} ms: {} fragments, P50 {:.2} m, P80 {:.2} m",
+        clock.elapsed, report.fragments, report.p50, report.p80
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(mass: f32, x: f32) -> Block {
+        Block {
+            origin: UVec3::ZERO,
+            original_position: Vec3::new(x, 0.0, 0.0),
+            size: Vec3::ONE,
+            mass,
+            lithology: 0,
+            grade: 0.0,
+            displaced_by: None,
+            max_impulse: 0.0,
+            peak_speed: 0.0,
+        }
+    }
+
+    fn bond(a: Entity, b: Entity) -> Bond {
+        Bond {
+            a,
+            b,
+            axis: Vec3::X,
+            rest_length: 1.0,
+            tensile_limit: 1.0,
+            shear_limit: 1.0,
+        }
+    }
+
+    #[test]
+    fn size_passing_interpolates() {
+        let cumulative = [(1.0, 50.0), (2.0, 100.0)];
+        assert_eq!(size_passing(&cumulative, 25.0), 0.5);
+        assert_eq!(size_passing(&cumulative, 75.0), 1.5);
+        assert_eq!(size_passing(&cumulative, 100.0), 2.0);
+    }
+
+    #[test]
+    fn bonded_blocks_are_one_fragment() {
+        let rock_mass = RockMass::new(Vec::new(), Vec::new(), Vec::new());
+        // four 1 m³ blocks, the first two still bonded and the last never moved
+        let density = rock_mass.lithologies[0].density;
+        let blocks: Vec<(Entity, Block, Vec3)> = (0..4)
+            .map(|i| {
+                let block = block(density, i as f32);
+                let moved = if i < 3 { Vec3::Y } else { Vec3::ZERO };
+                (Entity::from_raw(i), block, block.original_position + moved)
+            })
+            .collect();
+        let bonds = [bond(Entity::from_raw(0), Entity::from_raw(1))];
+        let report = FragmentationReport::build(
+            blocks
+                .iter()
+                .map(|(entity, block, translation)| (*entity, block, *translation)),
+            bonds.iter(),
+            &rock_mass,
+            None,
+        );
+        assert_eq!(report.fragments, 2);
+        assert_eq!(report.blocks, 3);
+        assert_eq!(report.in_situ_blocks, 1);
+        assert!((report.mass - 3.0 * density).abs() < 1e-2);
+        // a 1 m fragment with a third of the mass and a 2 m³ one, 2^(1/3) m across
+        let big = 2f32.cbrt();
+        assert!((report.top_size - big).abs() < 1e-4);
+        assert!((report.p20 - 0.6).abs() < 1e-4);
+        assert!((report.p50 - (1.0 + (big - 1.0) * 0.25)).abs() < 1e-4);
+        assert_eq!(report.curve.len(), CURVE_POINTS);
+        assert!((report.curve[0].passing - 100.0 / 3.0).abs() < 1e-3);
+        assert_eq!(report.curve[CURVE_POINTS - 1].passing, 100.0);
+        assert!(report.curve[0].kuz_ram.is_none());
+    }
+
+    #[test]
+    fn nothing_moved_leaves_an_empty_curve() {
+        let rock_mass = RockMass::new(Vec::new(), Vec::new(), Vec::new());
+        let block = block(1.0, 0.0);
+        let report = FragmentationReport::build(
+            [(Entity::from_raw(0), &block, Vec3::ZERO)].into_iter(),
+            [].iter(),
+            &rock_mass,
+            None,
+        );
+        assert_eq!(report.fragments, 0);
+        assert_eq!(report.in_situ_blocks, 1);
+        assert!(report.curve.is_empty());
+    }
+}
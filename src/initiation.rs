@@ -0,0 +1,150 @@
+use crate::blast_clock::*;
+use crate::simulation::SimulationRng;
+use crate::useful_structs::*;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use rand::Rng;
+
+/// A charge deck's detonator: the hole id and the deck's index in the hole's charge intervals
+pub type DeckId = (u32, usize);
+
+/// Sent exactly once per charge deck, on the frame its firing time is reached
+#[derive(Debug, Clone, Copy, Event)]
+pub struct DetonationEvent {
+    pub hole_id: u32,
+    pub deck: usize, // index of the deck in the hole's charge intervals
+    pub time: f32,   // actual firing time in milliseconds, scatter included
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct InitiationPlan {
+    // firing time in milliseconds of every deck, with the detonator scatter already drawn
+    pub firing_times: HashMap<DeckId, f32>,
+    // decks that have already gone off
+    pub detonated: HashSet<DeckId>,
+}
+
+impl InitiationPlan {
+    // draw the firing time of every deck that has not gone off yet
+    pub fn rebuild(&mut self, drill_holes: &DrillHoles, rng: &mut impl Rng) {
+        let mut firing_times = HashMap::new();
+        for drill_hole in drill_holes.0.iter() {
+            for (index, deck) in drill_hole.charge.decks() {
+                let deck_id = (drill_hole.id, index);
+                let firing_time = match self.firing_times.get(&deck_id) {
+                    // keep the time of decks that already fired, the past can't change
+                    Some(&time) if self.detonated.contains(&deck_id) => time,
+                    _ => {
+                        // every deck has its own detonator and so its own scatter
+                        let scatter = if drill_hole.scatter > 0.0 {
+                            rng.gen_range(-drill_hole.scatter..=drill_hole.scatter)
+                        } else {
+                            0.0
+                        };
+                        (drill_hole.nominal_firing_time() + deck.delay + scatter).max(0.0)
+                    }
+                };
+                firing_times.insert(deck_id, firing_time);
+            }
+        }
+        self.firing_times = firing_times;
+    }
+}
+
+// Fire each deck once the blast clock passes its firing time. Decks due within the same frame are
+// sent in firing order so the sequence is preserved even though the frame is longer than a delay
+pub fn initiation_system(
+    clock: Res<BlastClock>,
+    drill_holes: Res<DrillHoles>,
+    mut plan: ResMut<InitiationPlan>,
+    mut rng: ResMut<SimulationRng>,
+    mut detonations: EventWriter<DetonationEvent>,
+) {
+    if drill_holes.is_changed() {
+        plan.rebuild(&drill_holes, &mut rng.rng);
+    }
+
+    if clock.state == BlastState::Armed {
+        return;
+    }
+    let now = clock.elapsed;
+    let mut due: Vec<DetonationEvent> = plan
+        .firing_times
+        .iter()
+        .filter(|(id, firing_time)| **firing_time <= now && !plan.detonated.contains(*id))
+        .map(|(&(hole_id, deck), &time)| DetonationEvent {
+            hole_id,
+            deck,
+            time,
+        })
+        .collect();
+    due.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    for detonation in due {
+        plan.detonated.insert((detonation.hole_id, detonation.deck));
+        detonations.send(detonation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drill_holes() -> DrillHoles {
+        DrillHoles::new(
+            (0..5)
+                .map(|i| {
+                    let mut drill_hole = DrillHole::new(
+                        Vec3::new(i as f32 * 3.0, 0.0, 0.0),
+                        1.0,
+                        9.0,
+                        i as f32 * 17.0,
+                    );
+                    drill_hole.scatter = 4.0;
+                    drill_hole
+                })
+                .collect(),
+        )
+    }
+
+    fn firing_times(seed: u64) -> HashMap<DeckId, f32> {
+        let mut rng = SimulationRng::new(Some(seed));
+        let mut plan = InitiationPlan::default();
+        plan.rebuild(&drill_holes(), &mut rng.rng);
+        plan.firing_times
+    }
+
+    #[test]
+    fn seeded_runs_draw_the_same_scatter() {
+        let first = firing_times(42);
+        assert_eq!(first.len(), 5);
+        assert_eq!(first, firing_times(42));
+        assert_ne!(first, firing_times(43));
+        for (&(hole_id, _), &time) in first.iter() {
+            let nominal = (hole_id - 1) as f32 * 17.0;
+            assert!((time - nominal).abs() <= 4.0);
+        }
+    }
+
+    #[test]
+    fn reset_starts_the_sequence_over() {
+        let mut rng = SimulationRng::new(Some(42));
+        let mut plan = InitiationPlan::default();
+        plan.rebuild(&drill_holes(), &mut rng.rng);
+        let first = plan.firing_times.clone();
+        rng.reset(Some(42));
+        plan.rebuild(&drill_holes(), &mut rng.rng);
+        assert_eq!(plan.firing_times, first);
+    }
+
+    #[test]
+    fn fired_decks_keep_their_time() {
+        let mut rng = SimulationRng::new(Some(42));
+        let mut plan = InitiationPlan::default();
+        plan.rebuild(&drill_holes(), &mut rng.rng);
+        let fired = plan.firing_times[&(1, 1)];
+        plan.detonated.insert((1, 1));
+        plan.rebuild(&drill_holes(), &mut rng.rng);
+        assert_eq!(plan.firing_times[&(1, 1)], fired);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::lithology::*;
+use crate::useful_structs::RockJoint;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Size in m of the patches a joint plane is split into, each patch is either open or a rock
+/// bridge depending on the set's persistence
+const PERSISTENCE_PATCH: f32 = 1.0;
+
+fn default_persistence() -> f32 {
+    1.0
+}
+
+fn default_joint_strength() -> f32 {
+    0.1
+}
+
+/// A family of parallel joint planes, oriented the way geotechs log them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JointSet {
+    pub dip: f32,           // degrees below horizontal, 90 is a vertical joint
+    pub dip_direction: f32, // degrees clockwise from north (-z) the planes dip towards
+    pub spacing: f32,       // m between neighbouring planes
+    #[serde(default = "default_persistence")]
+    pub persistence: f32, // fraction of each plane that is actually open, the rest is rock bridges
+    pub friction: f32,      // friction coefficient of blocks sliding on the joint
+    #[serde(default = "default_joint_strength")]
+    pub strength: f32, // fraction of the intact bond strength left across the joint
+    #[serde(default)]
+    pub origin: Vec3, // any point on one of the planes
+}
+
+impl JointSet {
+    // unit normal of the planes, pointing up
+    pub fn normal(&self) -> Vec3 {
+        let dip = self.dip.to_radians();
+        let dip_direction = self.dip_direction.to_radians();
+        Vec3::new(
+            dip.sin() * dip_direction.sin(),
+            dip.cos(),
+            -dip.sin() * dip_direction.cos(),
+        )
+    }
+
+    // the `index`th plane of the set, plane 0 goes through `origin`
+    pub fn plane(&self, index: i32) -> RockJoint {
+        let normal = self.normal();
+        RockJoint {
+            origin: self.origin + normal * (index as f32 * self.spacing),
+            normal,
+            friction: self.friction,
+            strength: self.strength,
+        }
+    }
+
+    // index of the plane slab `point` lies in
+    fn slab(&self, point: Vec3) -> i32 {
+        ((point - self.origin).dot(self.normal()) / self.spacing).floor() as i32
+    }
+
+    /// The open joint of this set between two points, if any. Points in neighbouring blocks are
+    /// expected, when several planes lie between them the one nearest `a` is returned
+    pub fn crossing(&self, set_index: usize, a: Vec3, b: Vec3) -> Option<RockJoint> {
+        let (slab_a, slab_b) = (self.slab(a), self.slab(b));
+        if slab_a == slab_b {
+            return None;
+        }
+        let joint = self.plane(if slab_b > slab_a { slab_a + 1 } else { slab_a });
+        let point = joint.intersection(a, b)?;
+        if patch_roll(set_index, point) < self.persistence {
+            Some(joint)
+        } else {
+            None
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.dip >= 0.0 && self.dip <= 90.0) {
+            return Err(format!(
+                "dip must be between 0 and 90 degrees, got {}",
+                self.dip
+            ));
+        }
+        if !self.dip_direction.is_finite() {
+            return Err(format!(
+                "dip_direction must be a finite number, got {}",
+                self.dip_direction
+            ));
+        }
+        if !(self.spacing > 0.0) {
+            return Err(format!("spacing must be positive, got {}", self.spacing));
+        }
+        if !(self.persistence >= 0.0 && self.persistence <= 1.0) {
+            return Err(format!(
+                "persistence must be between 0 and 1, got {}",
+                self.persistence
+            ));
+        }
+        if !(self.friction >= 0.0) {
+            return Err(format!(
+                "friction must not be negative, got {}",
+                self.friction
+            ));
+        }
+        if !(self.strength >= 0.0 && self.strength <= 1.0) {
+            return Err(format!(
+                "strength must be between 0 and 1, got {}",
+                self.strength
+            ));
+        }
+        if !self.origin.is_finite() {
+            return Err("origin must be finite".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Stable pseudo random number in [0, 1) for the persistence patch holding `point`, so the same
+// bench always gets the same rock bridges
+fn patch_roll(set_index: usize, point: Vec3) -> f32 {
+    let cell = (point / PERSISTENCE_PATCH).floor().as_ivec3();
+    let mut hash = set_index as u32 ^ 0x9e37_79b9;
+    for value in [cell.x, cell.y, cell.z] {
+        hash = (hash ^ value as u32).wrapping_mul(0x85eb_ca6b);
+        hash ^= hash >> 13;
+    }
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    (hash >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Properties of the contact between two neighbouring blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaceProperties {
+    pub friction: f32, // infinite when no joint limits it, the lithology's friction applies then
+    pub strength: f32, // fraction of the intact bond strength, 1 for intact rock
+}
+
+impl Default for FaceProperties {
+    fn default() -> Self {
+        FaceProperties {
+            friction: f32::INFINITY,
+            strength: 1.0,
+        }
+    }
+}
+
+/// What the bench is made of: the lithologies laid out by their domains and every joint set
+/// cutting through them
+#[derive(Debug, Clone, Resource)]
+pub struct RockMass {
+    pub joint_sets: Vec<JointSet>,
+    pub lithologies: Vec<Lithology>, // the first one is the host rock filling everything else
+    pub domains: Vec<LithologyDomain>,
+}
+
+impl Default for RockMass {
+    fn default() -> Self {
+        RockMass::new(Vec::new(), Vec::new(), Vec::new())
+    }
+}
+
+impl RockMass {
+    pub fn new(
+        joint_sets: Vec<JointSet>,
+        mut lithologies: Vec<Lithology>,
+        domains: Vec<LithologyDomain>,
+    ) -> Self {
+        if lithologies.is_empty() {
+            lithologies.push(Lithology::default());
+        }
+        RockMass {
+            joint_sets,
+            lithologies,
+            domains,
+        }
+    }
+
+    /// Index in `lithologies` of the rock at `point`, the last domain containing it wins
+    pub fn lithology_at(&self, point: Vec3) -> usize {
+        self.domains
+            .iter()
+            .rev()
+            .find(|domain| domain.contains(point))
+            .and_then(|domain| {
+                self.lithologies
+                    .iter()
+                    .position(|lithology| lithology.name == domain.lithology())
+            })
+            .unwrap_or(0)
+    }
+
+    /// Contact between the blocks centered on `a` and `b`, the weakest joint crossing it wins
+    pub fn face(&self, a: Vec3, b: Vec3) -> FaceProperties {
+        let mut face = FaceProperties::default();
+        for (set_index, joint_set) in self.joint_sets.iter().enumerate() {
+            if let Some(joint) = joint_set.crossing(set_index, a, b) {
+                face.friction = face.friction.min(joint.friction);
+                face.strength = face.strength.min(joint.strength);
+            }
+        }
+        face
+    }
+
+    /// Contacts of a block with its six neighbours, `size` is the block size along x, y, z
+    pub fn block_faces(&self, center: Vec3, size: Vec3) -> [FaceProperties; 6] {
+        let offsets = [
+            Vec3::X * size.x,
+            Vec3::NEG_X * size.x,
+            Vec3::Y * size.y,
+            Vec3::NEG_Y * size.y,
+            Vec3::Z * size.z,
+            Vec3::NEG_Z * size.z,
+        ];
+        offsets.map(|offset| self.face(center, center + offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vertical joints striking north-south every 2 m, at x = 0, 2, 4...
+    fn vertical(persistence: f32) -> JointSet {
+        JointSet {
+            dip: 90.0,
+            dip_direction: 90.0,
+            spacing: 2.0,
+            persistence,
+            friction: 0.3,
+            strength: 0.2,
+            origin: Vec3::ZERO,
+        }
+    }
+
+    #[test]
+    fn faces_across_a_joint_take_its_friction() {
+        let rock_mass = RockMass::new(vec![vertical(1.0)], Vec::new(), Vec::new());
+        let jointed = FaceProperties {
+            friction: 0.3,
+            strength: 0.2,
+        };
+        assert_eq!(
+            rock_mass.face(Vec3::new(1.5, 0.5, 0.0), Vec3::new(2.5, 0.5, 0.0)),
+            jointed
+        );
+        // the other way round is the same joint
+        assert_eq!(
+            rock_mass.face(Vec3::new(2.5, 0.5, 0.0), Vec3::new(1.5, 0.5, 0.0)),
+            jointed
+        );
+        // intact between two joints
+        assert_eq!(
+            rock_mass.face(Vec3::new(0.5, 0.5, 0.0), Vec3::new(1.5, 0.5, 0.0)),
+            FaceProperties::default()
+        );
+
+        let faces = rock_mass.block_faces(Vec3::new(1.5, 0.5, 0.0), Vec3::ONE);
+        assert_eq!(faces[0], jointed);
+        assert!(faces[1..]
+            .iter()
+            .all(|face| *face == FaceProperties::default()));
+    }
+
+    #[test]
+    fn the_weakest_joint_wins() {
+        let bedding = JointSet {
+            dip: 0.0,
+            dip_direction: 0.0,
+            spacing: 1.0,
+            persistence: 1.0,
+            friction: 0.6,
+            strength: 0.05,
+            origin: Vec3::ZERO,
+        };
+        let rock_mass = RockMass::new(vec![vertical(1.0), bedding], Vec::new(), Vec::new());
+        // diagonally across both sets
+        let face = rock_mass.face(Vec3::new(1.5, 0.5, 0.0), Vec3::new(2.5, 1.5, 0.0));
+        assert_eq!(face.friction, 0.3);
+        assert_eq!(face.strength, 0.05);
+    }
+
+    #[test]
+    fn closed_joints_leave_the_rock_intact() {
+        let rock_mass = RockMass::new(vec![vertical(0.0)], Vec::new(), Vec::new());
+        assert_eq!(
+            rock_mass.face(Vec3::new(1.5, 0.5, 0.0), Vec3::new(2.5, 0.5, 0.0)),
+            FaceProperties::default()
+        );
+        assert!(vertical(1.5).validate().is_err());
+        assert!(vertical(0.5).validate().is_ok());
+    }
+}
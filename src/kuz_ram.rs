@@ -0,0 +1,352 @@
+use crate::explosives::ExplosiveLibrary;
+use crate::scenario::BlastScenario;
+use crate::useful_structs::DrillHoles;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Relative weight strength of TNT against ANFO, the reference of the Kuznetsov equation
+const TNT_RWS: f32 = 115.0;
+/// Number of sizes on the predicted curves
+const CURVE_POINTS: usize = 30;
+
+/// Site inputs of the empirical fragmentation models that can't be read off the drill holes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KuzRamSettings {
+    pub rock_factor: f32, // Cunningham's A, about 7 for medium rock, 10 to 13 for hard blocky rock
+    pub burden: Option<f32>, // m, taken from the pattern or guessed from the hole spacing if None
+    pub spacing: Option<f32>, // m, likewise
+    pub drilling_deviation: f32, // standard deviation of the drilling accuracy in m
+    pub max_size: Option<f32>, // largest block the rock mass breaks into (m), min(burden, spacing) if None
+}
+
+impl Default for KuzRamSettings {
+    fn default() -> Self {
+        KuzRamSettings {
+            rock_factor: 7.0,
+            burden: None,
+            spacing: None,
+            drilling_deviation: 0.0,
+            max_size: None,
+        }
+    }
+}
+
+impl KuzRamSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.rock_factor > 0.0) {
+            return Err(format!(
+                "rock_factor must be positive, got {}",
+                self.rock_factor
+            ));
+        }
+        for (name, value) in [
+            ("burden", self.burden),
+            ("spacing", self.spacing),
+            ("max_size", self.max_size),
+        ] {
+            if let Some(value) = value {
+                if !(value > 0.0) {
+                    return Err(format!("{} must be positive, got {}", name, value));
+                }
+            }
+        }
+        if !(self.drilling_deviation >= 0.0) {
+            return Err(format!(
+                "drilling_deviation must not be negative, got {}",
+                self.drilling_deviation
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PredictedPoint {
+    pub size: f32,    // m
+    pub kuz_ram: f32, // % passing of the Rosin-Rammler curve
+    pub swebrec: f32, // % passing of the Swebrec curve
+}
+
+/// Size distribution the Kuz-Ram and Swebrec models expect from the loaded holes
+#[derive(Debug, Clone, Serialize)]
+pub struct KuzRam {
+    // pattern averaged over the charged holes
+    pub burden: f32,          // m
+    pub spacing: f32,         // m
+    pub bench_height: f32,    // m
+    pub charge_length: f32,   // m per hole
+    pub diameter: f32,        // mm
+    pub charge_per_hole: f32, // kg
+    pub powder_factor: f32,   // kg/m^3
+    pub rws: f32,             // relative weight strength, ANFO = 100
+    // model parameters
+    pub x50: f32,        // median size in m
+    pub uniformity: f32, // Cunningham's n
+    pub x_max: f32,      // m
+    pub swebrec_b: f32,
+    pub p80_kuz_ram: f32,
+    pub p80_swebrec: f32,
+    pub curve: Vec<PredictedPoint>,
+}
+
+impl KuzRam {
+    /// Prediction for the charged holes. None if no hole is charged, or if burden or spacing has
+    /// to be guessed from the collars and there is only one hole to guess from
+    pub fn predict(
+        drill_holes: &DrillHoles,
+        library: &ExplosiveLibrary,
+        settings: &KuzRamSettings,
+    ) -> Option<Self> {
+        let mut holes = 0;
+        let (mut charge, mut energy_weighted, mut charge_length) = (0.0, 0.0, 0.0);
+        let (mut bench_height, mut diameter) = (0.0, 0.0);
+        for drill_hole in drill_holes.0.iter() {
+            let (mut hole_charge, mut hole_charge_length) = (0.0, 0.0);
+            for (_, deck) in drill_hole.charge.decks() {
+                let Some(product) = library.get(&deck.product) else {
+                    continue;
+                };
+                let mass = deck.mass(drill_hole.diameter, product);
+                hole_charge += mass;
+                hole_charge_length += deck.length();
+                energy_weighted += mass * product.relative_weight_strength();
+            }
+            if hole_charge <= 0.0 {
+                continue;
+            }
+            holes += 1;
+            charge += hole_charge;
+            charge_length += hole_charge_length;
+            bench_height += drill_hole.length * drill_hole.dip.to_radians().sin();
+            diameter += drill_hole.diameter;
+        }
+        if holes == 0 {
+            return None;
+        }
+        let count = holes as f32;
+        let (spacing, burden) = match (settings.spacing, settings.burden) {
+            (Some(spacing), Some(burden)) => (spacing, burden),
+            (spacing, burden) => {
+                let guess = nearest_collar_spacing(drill_holes)?;
+                (spacing.unwrap_or(guess), burden.unwrap_or(guess))
+            }
+        };
+        let bench_height = bench_height / count;
+        let charge_length = charge_length / count;
+        let diameter = diameter / count * 1000.0;
+        let charge_per_hole = charge / count;
+        let powder_factor = charge / (count * burden * spacing * bench_height);
+        let rws = energy_weighted / charge;
+
+        // Kuznetsov's median size in cm, with Cunningham's correction for the explosive strength
+        let x50 = settings.rock_factor
+            * powder_factor.powf(-0.8)
+            * charge_per_hole.powf(1.0 / 6.0)
+            * (TNT_RWS / rws).powf(19.0 / 20.0)
+            / 100.0;
+        let uniformity = ((2.2 - 14.0 * burden / diameter)
+            * ((1.0 + spacing / burden) / 2.0).sqrt()
+            * (1.0 - settings.drilling_deviation / burden)
+            * (charge_length / bench_height))
+            .max(0.1);
+        // Swebrec needs the top size above the median
+        let x_max = settings
+            .max_size
+            .unwrap_or(burden.min(spacing))
+            .max(2.0 * x50);
+        let swebrec_b = 2.0 * 2f32.ln() * (x_max / x50).ln() * uniformity;
+
+        let mut prediction = KuzRam {
+            burden,
+            spacing,
+            bench_height,
+            charge_length,
+            diameter,
+            charge_per_hole,
+            powder_factor,
+            rws,
+            x50,
+            uniformity,
+            x_max,
+            swebrec_b,
+            p80_kuz_ram: 0.0,
+            p80_swebrec: 0.0,
+            curve: Vec::new(),
+        };
+        prediction.p80_kuz_ram = prediction.kuz_ram_size(80.0);
+        prediction.p80_swebrec = prediction.swebrec_size(80.0);
+        // log spaced from a twentieth of the median up to the top size
+        let smallest = x50 / 20.0;
+        for i in 0..CURVE_POINTS {
+            let size = smallest * (x_max / smallest).powf(i as f32 / (CURVE_POINTS - 1) as f32);
+            prediction.curve.push(PredictedPoint {
+                size,
+                kuz_ram: prediction.kuz_ram_passing(size),
+                swebrec: prediction.swebrec_passing(size),
+            });
+        }
+        Some(prediction)
+    }
+
+    /// % passing `size` according to the Rosin-Rammler curve of Kuz-Ram
+    pub fn kuz_ram_passing(&self, size: f32) -> f32 {
+        100.0 * (1.0 - (-2f32.ln() * (size / self.x50).powf(self.uniformity)).exp())
+    }
+
+    pub fn kuz_ram_size(&self, percent: f32) -> f32 {
+        let passing = percent / 100.0;
+        self.x50 * ((1.0 / (1.0 - passing)).ln() / 2f32.ln()).powf(1.0 / self.uniformity)
+    }
+
+    /// % passing `size` according to the Swebrec function
+    pub fn swebrec_passing(&self, size: f32) -> f32 {
+        if size >= self.x_max {
+            return 100.0;
+        }
+        let ratio = (self.x_max / size).ln() / (self.x_max / self.x50).ln();
+        100.0 / (1.0 + ratio.powf(self.swebrec_b))
+    }
+
+    pub fn swebrec_size(&self, percent: f32) -> f32 {
+        let passing = percent / 100.0;
+        self.x_max
+            * (-(1.0 / passing - 1.0).powf(1.0 / self.swebrec_b) * (self.x_max / self.x50).ln())
+                .exp()
+    }
+}
+
+// Median horizontal distance from each collar to the closest other one, stands in for both burden
+// and spacing when the scenario gives neither
+fn nearest_collar_spacing(drill_holes: &DrillHoles) -> Option<f32> {
+    let mut distances: Vec<f32> = drill_holes
+        .0
+        .iter()
+        .enumerate()
+        .filter_map(|(i, drill_hole)| {
+            drill_holes
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, other)| other)
+                .map(|other| drill_hole.collar.xz().distance(other.collar.xz()))
+                .filter(|distance| *distance > 0.0)
+                .min_by(|a, b| a.total_cmp(b))
+        })
+        .collect();
+    if distances.is_empty() {
+        return None;
+    }
+    distances.sort_by(|a, b| a.total_cmp(b));
+    Some(distances[distances.len() / 2])
+}
+
+/// The current Kuz-Ram / Swebrec prediction, None while KuzRam::predict has nothing to go on: no
+/// hole is charged, or a single hole without the burden and spacing in `kuz_ram`
+#[derive(Debug, Default, Resource)]
+pub struct FragmentationPrediction(pub Option<KuzRam>);
+
+// Redo the prediction whenever the holes, products or site inputs change
+pub fn fragmentation_prediction_system(
+    drill_holes: Res<DrillHoles>,
+    library: Res<ExplosiveLibrary>,
+    scenario: Res<BlastScenario>,
+    mut prediction: ResMut<FragmentationPrediction>,
+) {
+    if !(drill_holes.is_changed() || library.is_changed() || scenario.is_changed()) {
+        return;
+    }
+    prediction.0 = KuzRam::predict(&drill_holes, &library, &scenario.kuz_ram);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explosives::ExplosiveCharge;
+    use crate::useful_structs::DrillHole;
+
+    // 9 m vertical holes of 100 mm, 6 m of ANFO under 3 m of stemming, 37.7 kg each
+    fn hole(x: f32, product: &str) -> DrillHole {
+        let mut drill_hole = DrillHole::new(Vec3::new(x, 0.0, 0.0), 1.0, 9.0, 0.0);
+        drill_hole.diameter = 0.1;
+        drill_hole.charge = ExplosiveCharge::single_deck(product, 6.0, 3.0, 9.0);
+        drill_hole
+    }
+
+    fn settings() -> KuzRamSettings {
+        KuzRamSettings {
+            burden: Some(3.0),
+            spacing: Some(4.0),
+            ..default()
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-3 * b.abs()
+    }
+
+    #[test]
+    fn prediction_for_a_known_pattern() {
+        let drill_holes = DrillHoles::new(vec![hole(0.0, "ANFO"), hole(4.0, "ANFO")]);
+        let prediction =
+            KuzRam::predict(&drill_holes, &ExplosiveLibrary::default(), &settings()).unwrap();
+        assert!(close(prediction.charge_per_hole, 37.699));
+        assert!(close(prediction.charge_length, 6.0));
+        assert!(close(prediction.bench_height, 9.0));
+        assert!(close(prediction.diameter, 100.0));
+        assert!(close(prediction.rws, 100.0));
+        // 37.7 kg over 3 x 4 x 9 m
+        assert!(close(prediction.powder_factor, 0.34907));
+        // 7 x 0.349^-0.8 x 37.7^(1/6) x (115 / 100)^(19/20) cm
+        assert!(close(prediction.x50, 0.33975));
+        // (2.2 - 14 x 3 / 100) x √((1 + 4 / 3) / 2) x 6 / 9
+        assert!(close(prediction.uniformity, 1.28175));
+        assert_eq!(prediction.x_max, 3.0);
+        assert!(close(prediction.swebrec_b, 3.87033));
+        assert!(close(prediction.p80_kuz_ram, 0.65552));
+        assert!(close(prediction.p80_swebrec, 0.65455));
+        assert_eq!(prediction.curve.len(), CURVE_POINTS);
+    }
+
+    #[test]
+    fn sizes_and_passing_are_inverse() {
+        let drill_holes = DrillHoles::new(vec![hole(0.0, "ANFO"), hole(4.0, "ANFO")]);
+        let prediction =
+            KuzRam::predict(&drill_holes, &ExplosiveLibrary::default(), &settings()).unwrap();
+        assert!(close(prediction.kuz_ram_size(50.0), prediction.x50));
+        assert!(close(prediction.swebrec_size(50.0), prediction.x50));
+        for percent in [10.0, 50.0, 90.0] {
+            let size = prediction.kuz_ram_size(percent);
+            assert!(close(prediction.kuz_ram_passing(size), percent));
+            let size = prediction.swebrec_size(percent);
+            assert!(close(prediction.swebrec_passing(size), percent));
+        }
+        assert_eq!(prediction.swebrec_passing(prediction.x_max), 100.0);
+    }
+
+    #[test]
+    fn holes_without_a_known_product_are_left_out() {
+        let drill_holes = DrillHoles::new(vec![
+            hole(0.0, "ANFO"),
+            hole(4.0, "ANFO"),
+            hole(8.0, "Mystery"),
+        ]);
+        let prediction =
+            KuzRam::predict(&drill_holes, &ExplosiveLibrary::default(), &settings()).unwrap();
+        assert!(close(prediction.charge_length, 6.0));
+        assert!(close(prediction.powder_factor, 0.34907));
+    }
+
+    #[test]
+    fn burden_and_spacing_are_guessed_from_the_collars() {
+        let library = ExplosiveLibrary::default();
+        let drill_holes = DrillHoles::new(vec![hole(0.0, "ANFO"), hole(4.0, "ANFO")]);
+        let prediction = KuzRam::predict(&drill_holes, &library, &default()).unwrap();
+        assert_eq!((prediction.burden, prediction.spacing), (4.0, 4.0));
+        // nothing to guess from with one hole
+        let drill_holes = DrillHoles::new(vec![hole(0.0, "ANFO")]);
+        assert!(KuzRam::predict(&drill_holes, &library, &default()).is_none());
+        assert!(KuzRam::predict(&drill_holes, &library, &settings()).is_some());
+    }
+}
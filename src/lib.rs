@@ -9,11 +9,33 @@ mod canvas_view;
 use canvas_view::*;
 
 mod bevy_app;
+pub use bevy_app::init_app_with_scenario;
 
 // Elliot Imports
 mod setup;
+mod simulation;
+mod surface_import;
 mod useful_structs;
+mod voronoi;
 mod drill_hole_go_boom;
+pub mod explosives;
+pub mod flyrock;
+mod airblast;
+pub mod blast_report;
+mod vibration;
+pub mod fragmentation;
+mod drill_hole_visuals;
+mod initiation;
+mod joints;
+mod kuz_ram;
+mod lithology;
+pub mod muckpile;
+pub mod scenario;
+mod blast_clock;
+mod bench_geometry;
+mod blast_pattern;
+mod bonds;
+pub mod octree;
 
 // Define the main WorkerApp struct
 pub struct WorkerApp {
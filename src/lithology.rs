@@ -0,0 +1,215 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// UCS in MPa the bond strengths are given for, stronger rock scales them up
+pub const REFERENCE_UCS: f32 = 100.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lithology {
+    // a rock type the bench can be built from
+    pub name: String,
+    pub density: f32,     // kg/m^3
+    pub ucs: f32,         // uniaxial compressive strength in MPa
+    pub friction: f32,    // friction coefficient of intact block faces
+    pub restitution: f32, // bounciness of the blocks, 0 to 1
+    pub color: [f32; 3],  // srgb
+    #[serde(default)]
+    pub grade: f32, // ore grade, 0 for waste
+}
+
+impl Default for Lithology {
+    // the plain red bench we had before lithologies
+    fn default() -> Self {
+        Lithology {
+            name: "Rock".to_string(),
+            density: 2600.0,
+            ucs: REFERENCE_UCS,
+            friction: 0.7,
+            restitution: 0.1,
+            color: [1.0, 0.0, 0.0],
+            grade: 0.0,
+        }
+    }
+}
+
+impl Lithology {
+    // bond strengths are scaled by this
+    pub fn strength_factor(&self) -> f32 {
+        self.ucs / REFERENCE_UCS
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("lithology name must not be empty".to_string());
+        }
+        if !(self.density > 0.0) {
+            return Err(format!(
+                "{}: density must be positive, got {}",
+                self.name, self.density
+            ));
+        }
+        if !(self.ucs > 0.0) {
+            return Err(format!(
+                "{}: ucs must be positive, got {}",
+                self.name, self.ucs
+            ));
+        }
+        if !(self.friction >= 0.0) {
+            return Err(format!(
+                "{}: friction must not be negative, got {}",
+                self.name, self.friction
+            ));
+        }
+        if !(self.restitution >= 0.0 && self.restitution <= 1.0) {
+            return Err(format!(
+                "{}: restitution must be between 0 and 1, got {}",
+                self.name, self.restitution
+            ));
+        }
+        if !self.color.iter().all(|c| (0.0..=1.0).contains(c)) {
+            return Err(format!(
+                "{}: color channels must be between 0 and 1",
+                self.name
+            ));
+        }
+        if !(self.grade >= 0.0) {
+            return Err(format!(
+                "{}: grade must not be negative, got {}",
+                self.name, self.grade
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Part of the bench made of one lithology, later domains are laid over earlier ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LithologyDomain {
+    /// Everything between two elevations
+    Layer {
+        lithology: String,
+        bottom: f32,
+        top: f32,
+    },
+    /// An axis aligned box, e.g. an ore lens
+    Box {
+        lithology: String,
+        min: Vec3,
+        max: Vec3,
+    },
+}
+
+impl LithologyDomain {
+    pub fn lithology(&self) -> &str {
+        match self {
+            LithologyDomain::Layer { lithology, .. } => lithology,
+            LithologyDomain::Box { lithology, .. } => lithology,
+        }
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        match self {
+            LithologyDomain::Layer { bottom, top, .. } => point.y >= *bottom && point.y < *top,
+            LithologyDomain::Box { min, max, .. } => {
+                point.cmpge(*min).all() && point.cmplt(*max).all()
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            LithologyDomain::Layer { bottom, top, .. } => {
+                if !(bottom.is_finite() && top.is_finite() && top > bottom) {
+                    return Err(format!(
+                        "layer top ({}) must be above its bottom ({})",
+                        top, bottom
+                    ));
+                }
+            }
+            LithologyDomain::Box { min, max, .. } => {
+                if !(min.is_finite() && max.is_finite() && max.cmpgt(*min).all()) {
+                    return Err(format!("box max {} must be above its min {}", max, min));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joints::RockMass;
+
+    fn lithology(name: &str, ucs: f32) -> Lithology {
+        Lithology {
+            name: name.to_string(),
+            ucs,
+            ..default()
+        }
+    }
+
+    // waste with an ore layer from 2 to 4 m and a box of hard rock cutting through it
+    fn rock_mass() -> RockMass {
+        RockMass::new(
+            Vec::new(),
+            vec![
+                lithology("Waste", 80.0),
+                lithology("Ore", 60.0),
+                lithology("Dyke", 200.0),
+            ],
+            vec![
+                LithologyDomain::Layer {
+                    lithology: "Ore".to_string(),
+                    bottom: 2.0,
+                    top: 4.0,
+                },
+                LithologyDomain::Box {
+                    lithology: "Dyke".to_string(),
+                    min: Vec3::new(5.0, 0.0, 0.0),
+                    max: Vec3::new(6.0, 10.0, 10.0),
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn domains_pick_the_lithology() {
+        let rock_mass = rock_mass();
+        assert_eq!(rock_mass.lithology_at(Vec3::new(1.0, 1.0, 1.0)), 0);
+        assert_eq!(rock_mass.lithology_at(Vec3::new(1.0, 3.0, 1.0)), 1);
+        // the top of a layer belongs to the one above
+        assert_eq!(rock_mass.lithology_at(Vec3::new(1.0, 2.0, 1.0)), 1);
+        assert_eq!(rock_mass.lithology_at(Vec3::new(1.0, 4.0, 1.0)), 0);
+        // the box is listed later, so it wins inside the layer
+        assert_eq!(rock_mass.lithology_at(Vec3::new(5.5, 3.0, 1.0)), 2);
+        assert_eq!(rock_mass.lithology_at(Vec3::new(6.0, 3.0, 1.0)), 1);
+    }
+
+    #[test]
+    fn unknown_lithologies_fall_back_to_the_host_rock() {
+        let rock_mass = RockMass::new(
+            Vec::new(),
+            Vec::new(),
+            vec![LithologyDomain::Layer {
+                lithology: "Missing".to_string(),
+                bottom: 0.0,
+                top: 10.0,
+            }],
+        );
+        assert_eq!(rock_mass.lithologies[0], Lithology::default());
+        assert_eq!(rock_mass.lithology_at(Vec3::new(1.0, 5.0, 1.0)), 0);
+    }
+
+    #[test]
+    fn strength_and_checks() {
+        assert_eq!(lithology("Dyke", 200.0).strength_factor(), 2.0);
+        assert!(lithology("Soft", 0.0).validate().is_err());
+        let layer = LithologyDomain::Layer {
+            lithology: "Ore".to_string(),
+            bottom: 4.0,
+            top: 2.0,
+        };
+        assert!(layer.validate().is_err());
+    }
+}
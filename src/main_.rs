@@ -1,166 +1,208 @@
 use bevy::prelude::*;
-use bevy_flycam::FlyCam;
-use bevy_flycam::NoCameraPlayerPlugin;
-use bevy_rapier3d::prelude::*;
-use drill_hole_go_boom::*;
-use setup::*;
-use useful_structs::*;
+use bevy_in_web_worker::blast_report::*;
+use bevy_in_web_worker::explosives::*;
+use bevy_in_web_worker::flyrock::*;
+use bevy_in_web_worker::fragmentation::*;
+use bevy_in_web_worker::init_app_with_scenario;
+use bevy_in_web_worker::muckpile::*;
+use bevy_in_web_worker::octree::*;
+use bevy_in_web_worker::scenario::*;
 
-// This cfg attribute ensures this code only runs when targeting wasm
-#[cfg(target_arch = "wasm32")]
-use wasm_bindgen::prelude::*;
-
-mod drill_hole_go_boom;
-mod setup;
-mod useful_structs;
-
-#[derive(Component)]
-struct TouchCamera {
-    sensitivity: f32,
-}
-
-// This is the entry point when not compiling to web
-#[cfg(not(target_arch = "wasm32"))]
+// Native viewer, the same app as the web worker plus the reports written to disk
+// Usage: `bevy-in-web-worker [scenario.json | scenario.ron] [explosives.json]`
 fn main() {
-    run();
-}
-
-// This is the entry point when compiling to web
-#[cfg(target_arch = "wasm32")]
-#[wasm_bindgen(start)]
-pub fn start() -> Result<(), JsValue> {
-    run();
-    Ok(())
-}
-
-fn run() {
-    let bench_dimensions: [f32; 3] = [20.0, 4.0, 10.0];
-    let bench_resolution: [f32; 3] = [0.2, 0.2, 0.2];
-    let bench_position: [f32; 3] = [0.0, 0.1, 0.0];
-    let drill_hole_1_position: (f32, f32) = (6.0, 5.0);
-    let drill_hole_1_radius: f32 = 1.0;
-    let drill_hole_1_height: f32 = 3.6;
-    let drill_hole_2_position: (f32, f32) = (14.0, 5.0);
-    let drill_hole_2_radius: f32 = 1.0;
-    let drill_hole_2_height: f32 = 3.6;
-
-    let drill_hole_1 = DrillHole {
-        position: Vec3::new(drill_hole_1_position.0, 0.0, drill_hole_1_position.1),
-        radius: drill_hole_1_radius,
-        height: drill_hole_1_height,
-        timing: 0.0,
+    let library = match std::env::args().nth(2) {
+        Some(path) => match ExplosiveLibrary::read_products_path(&path) {
+            Ok(products) => products,
+            Err(e) => {
+                eprintln!("could not load explosive library: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Vec::new(),
     };
-    let drill_hole_2 = DrillHole {
-        position: Vec3::new(drill_hole_2_position.0, 0.0, drill_hole_2_position.1),
-        radius: drill_hole_2_radius,
-        height: drill_hole_2_height,
-        timing: 2.0,
+    let scenario = match std::env::args().nth(1) {
+        Some(path) => BlastScenario::from_path(&path, &library),
+        None => BlastScenario {
+            explosives: library,
+            ..default_scenario()
+        }
+        .finish(),
     };
-    let drill_holes_vec: DrillHoles = DrillHoles(vec![drill_hole_1, drill_hole_2]);
-
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                fit_canvas_to_parent: true,
-                prevent_default_event_handling: false,
-                ..default()
-            }),
-            ..default()
-        }))
-        .add_plugins(NoCameraPlayerPlugin)
-        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugins(RapierDebugRenderPlugin::default())
-        .add_systems(Startup, setup_graphics)
-        .add_systems(Startup, setup_ground)
-        .insert_resource(drill_holes_vec.clone())
-        .add_systems(
-            Startup,
-            move |commands: Commands,
-                  meshes: ResMut<Assets<Mesh>>,
-                  materials: ResMut<Assets<StandardMaterial>>,
-                  drill_holes_vec: Res<DrillHoles>| {
-                setup_bench(
-                    commands,
-                    meshes,
-                    materials,
-                    &bench_dimensions,
-                    &bench_resolution,
-                    &bench_position,
-                    &drill_holes_vec,
-                );
-            },
-        )
-        .add_systems(Update, handle_touch_input)
-        .add_systems(
-            Update,
-            move |commands: Commands,
-                  rapier_context: ResMut<RapierContext>,
-                  time: Res<Time>,
-                  drill_holes: Res<DrillHoles>,
-                  entity_query: Query<(&Transform, Option<&mut ExternalImpulse>)>| {
-                drill_hole_go_boom_system(
-                    commands,
-                    rapier_context,
-                    drill_holes,
-                    time,
-                    64.0,
-                    entity_query,
-                );
-            },
-        )
-        .run();
+    match scenario {
+        Ok(scenario) => run(scenario),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
-fn setup_graphics(mut commands: Commands) {
-    // Add a camera so we can see the debug-render.
-
-    let look_from = Vec3::new(10.0, 5.0, 30.0);
-    let look_at = Vec3::new(10.0, 2.0, 5.0);
-    commands.spawn((
-        Camera3dBundle {
-            transform: Transform::from_xyz(look_from.x, look_from.y, look_from.z)
-                .looking_at(look_at, Vec3::Y),
-            ..Default::default()
-        },
-        TouchCamera { sensitivity: 0.005 },
-        FlyCam,
-    ));
+// the native build runs a finer bench than the worker by default, merged into bigger blocks away
+// from the holes to keep the block count down
+fn default_scenario() -> BlastScenario {
+    BlastScenario {
+        bench_resolution: [0.2, 0.2, 0.2],
+        adaptive: Some(AdaptiveResolution {
+            levels: 2,
+            near_hole_distance: 2.0,
+            near_face_distance: 1.0,
+        }),
+        ..default()
+    }
 }
 
-fn handle_touch_input(
-    touches: Res<Touches>,
-    mut query: Query<(&mut Transform, &TouchCamera)>,
-    time: Res<Time>,
-) {
-    let (mut transform, touch_camera) = query.single_mut();
+fn run(scenario: BlastScenario) {
+    let mut app = init_app_with_scenario(scenario);
+    app.add_systems(
+        Update,
+        write_fragmentation_report.after(fragmentation_system),
+    )
+    .add_systems(Update, write_muckpile_report.after(muckpile_system))
+    .add_systems(Update, write_flyrock_report.after(fragmentation_system))
+    .add_systems(Update, write_blast_report.after(blast_report_system))
+    .run();
+}
 
-    // Handle two-finger pan
-    if let Some(touch_a) = touches.get_pressed(0) {
-        if let Some(touch_b) = touches.get_pressed(1) {
-            let delta_a = touch_a.delta();
-            let delta_b = touch_b.delta();
-            let avg_delta = (delta_a + delta_b) / 2.0;
-            transform.translation += Vec3::new(
-                -avg_delta.x * touch_camera.sensitivity,
-                avg_delta.y * touch_camera.sensitivity,
-                0.0,
-            );
-        }
+// print the size distribution once the muck settles and save it next to the binary's working dir
+fn write_fragmentation_report(report: Res<FragmentationReport>) {
+    if !report.is_changed() || report.time.is_none() {
+        return;
+    }
+    println!(
+        "{} fragments from {} blocks ({} left in situ), P20 {:.3} m, P50 {:.3} m, P80 {:.3} m, top size {:.3} m",
+        report.fragments,
+        report.blocks,
+        report.in_situ_blocks,
+        report.p20,
+        report.p50,
+        report.p80,
+        report.top_size
+    );
+    if let Some(predicted) = &report.predicted {
+        println!(
+            "Kuz-Ram predicts X50 {:.3} m, n {:.2}, P80 {:.3} m (Swebrec P80 {:.3} m)",
+            predicted.x50, predicted.uniformity, predicted.p80_kuz_ram, predicted.p80_swebrec
+        );
+    }
+    println!("    size  passing  kuz-ram  swebrec");
+    for point in report.curve.iter() {
+        println!(
+            "{:>6.3} m {:>6.1} % {:>6.1} % {:>6.1} %",
+            point.size,
+            point.passing,
+            point.kuz_ram.unwrap_or(f32::NAN),
+            point.swebrec.unwrap_or(f32::NAN)
+        );
     }
+    if let Err(e) = std::fs::write("fragmentation_report.json", report.to_json()) {
+        eprintln!("could not write fragmentation_report.json: {}", e);
+    }
+}
 
-    // Handle pinch-to-zoom
-    if touches.iter().count() == 2 {
-        let touch_a = touches.iter().next().unwrap();
-        let touch_b = touches.iter().nth(1).unwrap();
+// list the flyrock once the muck has settled, it is already tracked and drawn during the blast
+fn write_flyrock_report(fragmentation: Res<FragmentationReport>, report: Res<FlyrockReport>) {
+    if !fragmentation.is_changed() || fragmentation.time.is_none() {
+        return;
+    }
+    println!(
+        "Flyrock: {} fragments, fastest block {:.1} m/s, furthest tracked {:.1} m from the blast",
+        report.fragments.len(),
+        report.max_speed,
+        report.max_distance
+    );
+    for fragment in report.fragments.iter() {
+        println!(
+            "  block {} ({}) from {} at {:.1} m/s, {:.1} m out ({:?})",
+            fragment.entity,
+            fragment.origin,
+            fragment.original_position,
+            fragment.peak_speed,
+            fragment.max_distance,
+            fragment.reason
+        );
+    }
+    if let Err(e) = std::fs::write("flyrock_report.json", report.to_json()) {
+        eprintln!("could not write flyrock_report.json: {}", e);
+    }
+}
 
-        let prev_distance = touch_a
-            .previous_position()
-            .distance(touch_b.previous_position());
-        let current_distance = touch_a.position().distance(touch_b.position());
+// print the predicted vibration and airblast whenever the design changes, before anything is fired
+fn write_blast_report(report: Res<BlastReport>) {
+    if !report.is_changed() || report.decks == 0 {
+        return;
+    }
+    println!(
+        "{} decks, {:.1} kg in total, at most {:.1} kg per delay (at {} ms)",
+        report.decks, report.total_charge, report.max_charge_per_delay, report.max_charge_time
+    );
+    for overlap in report.overlaps.iter() {
+        println!(
+            "  {} decks within {} ms at {} ms fire {:.1} kg together, {:.1} kg if spread out",
+            overlap.decks.len(),
+            overlap.span,
+            overlap.time,
+            overlap.charge,
+            overlap.largest_deck
+        );
+    }
+    for monitor in report.monitors.iter() {
+        let vibration = &monitor.vibration;
+        println!(
+            "  {}: PPV {:.1} mm/s from {:.1} kg at {:.1} m (SD {:.1}){}",
+            monitor.name,
+            vibration.ppv,
+            vibration.charge,
+            vibration.distance,
+            vibration.scaled_distance,
+            if vibration.exceeds_limit {
+                ", over the limit"
+            } else {
+                ""
+            }
+        );
+        let airblast = &monitor.airblast;
+        println!(
+            "  {}: airblast {:.0} dBL from {:.1} kg at {:.1} m (SD {:.1}, confinement {:.2}){}",
+            monitor.name,
+            airblast.overpressure,
+            airblast.charge,
+            airblast.distance,
+            airblast.scaled_distance,
+            airblast.confinement,
+            if airblast.exceeds_limit {
+                ", over the limit"
+            } else {
+                ""
+            }
+        );
+    }
+    if !report.within_limits {
+        println!("  a monitor is predicted over its limits, check the design before firing");
+    }
+    if let Err(e) = std::fs::write("blast_report.json", report.to_json()) {
+        eprintln!("could not write blast_report.json: {}", e);
+    }
+}
 
-        let zoom_factor =
-            (prev_distance - current_distance) * touch_camera.sensitivity * time.delta_seconds();
-        let forward = transform.forward();
-        transform.translation += forward * zoom_factor * 50.0;
+// same for the muckpile, the section profiles go to a CSV for plotting
+fn write_muckpile_report(report: Res<MuckpileReport>) {
+    if !report.is_changed() || report.time.is_none() {
+        return;
+    }
+    println!(
+        "Muckpile: max throw {:.2} m, heave {:.2} m, swell factor {:.2}, centroid moved {:.2} m",
+        report.max_throw,
+        report.heave,
+        report.swell_factor,
+        report.centroid_shift.length()
+    );
+    for (path, contents) in [
+        ("muckpile_report.json", report.to_json()),
+        ("muckpile_sections.csv", report.to_csv()),
+    ] {
+        if let Err(e) = std::fs::write(path, contents) {
+            eprintln!("could not write {}: {}", path, e);
+        }
     }
 }
@@ -0,0 +1,537 @@
+use crate::fragmentation::FragmentationReport;
+use crate::scenario::BlastScenario;
+use crate::useful_structs::Block;
+use bevy::color::palettes::css::{GOLD, LIGHT_GRAY};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Upper bound on the samples of one section profile, a long section at a fine spacing would
+/// otherwise allocate without limit
+const MAX_SECTION_SAMPLES: usize = 10_000;
+
+/// Rough upper bound on the cells the swell is measured over, the cells are made coarser than
+/// `sample_spacing` on benches that would need more
+const MAX_SWELL_CELLS: f32 = 1_000_000.0;
+
+/// A vertical cut through the muckpile, given by its line in plan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MuckSection {
+    pub start: Vec3, // only x and z are used
+    pub end: Vec3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MuckpileSettings {
+    pub sections: Vec<MuckSection>, // one through the bench along the throw if empty
+    pub section_width: f32,         // m either side of a section line that blocks are taken from
+    pub sample_spacing: f32,        // m between profile samples, and the smallest swell cell
+}
+
+impl Default for MuckpileSettings {
+    fn default() -> Self {
+        MuckpileSettings {
+            sections: Vec::new(),
+            section_width: 1.0,
+            sample_spacing: 0.5,
+        }
+    }
+}
+
+impl MuckpileSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.section_width > 0.0) {
+            return Err(format!(
+                "section_width must be positive, got {}",
+                self.section_width
+            ));
+        }
+        if !(self.sample_spacing > 0.0) {
+            return Err(format!(
+                "sample_spacing must be positive, got {}",
+                self.sample_spacing
+            ));
+        }
+        for (i, section) in self.sections.iter().enumerate() {
+            if !(section.start.is_finite() && section.end.is_finite()) {
+                return Err(format!("sections[{}]: start and end must be finite", i));
+            }
+            if section.start.xz() == section.end.xz() {
+                return Err(format!("sections[{}]: start and end are the same point", i));
+            }
+            let samples = section.start.xz().distance(section.end.xz()) / self.sample_spacing;
+            if samples >= MAX_SECTION_SAMPLES as f32 {
+                return Err(format!(
+                    "sections[{}]: would take {} samples, the limit is {}",
+                    i,
+                    samples.floor() + 1.0,
+                    MAX_SECTION_SAMPLES
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ProfilePoint {
+    pub distance: f32,                // m along the section from its start
+    pub position: Vec3,               // on the section line at the floor
+    pub height: Option<f32>,          // top of the muck, None where no block is left
+    pub original_height: Option<f32>, // top of the bench before the blast
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionProfile {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub points: Vec<ProfilePoint>,
+}
+
+/// Shape of the muckpile once it has settled, compared with the bench it came from
+#[derive(Debug, Clone, Default, Resource, Serialize)]
+pub struct MuckpileReport {
+    pub time: Option<f32>, // blast clock time in ms, None until the muck has settled
+    pub sections: Vec<SectionProfile>,
+    pub max_throw: f32,           // furthest any block moved horizontally, m
+    pub max_throw_position: Vec3, // where that block came to rest
+    pub heave: f32,               // highest any block was lifted, m
+    pub swell_factor: f32,        // volume under the muck surface over the volume under the bench
+    pub original_centroid: Vec3,
+    pub displaced_centroid: Vec3,
+    pub centroid_shift: Vec3,
+}
+
+// where a block's top is now and where it was before the blast
+struct BlockTop {
+    position: Vec3,
+    original_position: Vec3,
+    top: f32,
+    original_top: f32,
+    footprint: Vec2, // size in plan
+}
+
+// Cells of a `cell` m grid whose centres a footprint covers, along an axis where it covers none
+// the cell under its centre
+fn footprint_cells(center: Vec2, footprint: Vec2, cell: f32) -> impl Iterator<Item = IVec2> {
+    let span = |center: f32, size: f32| {
+        let first = ((center - size / 2.0) / cell - 0.5).ceil() as i32;
+        let last = ((center + size / 2.0) / cell - 0.5).floor() as i32;
+        if first > last {
+            let under = (center / cell).floor() as i32;
+            under..=under
+        } else {
+            first..=last
+        }
+    };
+    let columns = span(center.x, footprint.x);
+    span(center.y, footprint.y)
+        .flat_map(move |row| columns.clone().map(move |column| IVec2::new(column, row)))
+}
+
+impl MuckpileReport {
+    /// Measure the muck from the blocks' current and original positions, `blocks` are the
+    /// blocks with their current translation
+    pub fn build<'a>(
+        blocks: impl Iterator<Item = (&'a Block, Vec3)>,
+        settings: &MuckpileSettings,
+    ) -> Self {
+        let mut report = MuckpileReport::default();
+        let mut tops = Vec::new();
+        let (mut mass, mut moment, mut original_moment) = (0.0, Vec3::ZERO, Vec3::ZERO);
+        let mut floor = f32::INFINITY;
+        for (block, translation) in blocks {
+            let half_height = block.size.y / 2.0;
+            tops.push(BlockTop {
+                position: translation,
+                original_position: block.original_position,
+                top: translation.y + half_height,
+                original_top: block.original_position.y + half_height,
+                footprint: block.size.xz(),
+            });
+            floor = floor.min(block.original_position.y - half_height);
+            mass += block.mass;
+            moment += translation * block.mass;
+            original_moment += block.original_position * block.mass;
+
+            let movement = block.movement(translation);
+            let throw = movement.xz().length();
+            if throw > report.max_throw {
+                report.max_throw = throw;
+                report.max_throw_position = translation;
+            }
+            report.heave = report.heave.max(movement.y);
+        }
+        if tops.is_empty() {
+            return report;
+        }
+        report.original_centroid = original_moment / mass;
+        report.displaced_centroid = moment / mass;
+        report.centroid_shift = report.displaced_centroid - report.original_centroid;
+
+        // loose over solid volume, both measured as the space under the top surface so the
+        // gaps between blocks only count after the blast. Blocks are taken as unrotated in plan
+        let area: f32 = tops
+            .iter()
+            .map(|top| top.footprint.x * top.footprint.y)
+            .sum();
+        let cell = settings.sample_spacing.max((area / MAX_SWELL_CELLS).sqrt());
+        let mut surface: HashMap<IVec2, f32> = HashMap::new();
+        let mut original_surface: HashMap<IVec2, f32> = HashMap::new();
+        for top in tops.iter() {
+            for key in footprint_cells(top.position.xz(), top.footprint, cell) {
+                let height = surface.entry(key).or_insert(floor);
+                *height = height.max(top.top);
+            }
+            for key in footprint_cells(top.original_position.xz(), top.footprint, cell) {
+                let height = original_surface.entry(key).or_insert(floor);
+                *height = height.max(top.original_top);
+            }
+        }
+        let volume = |surface: &HashMap<IVec2, f32>| {
+            surface
+                .values()
+                .map(|top| (top - floor) * cell * cell)
+                .sum::<f32>()
+        };
+        report.swell_factor = volume(&surface) / volume(&original_surface);
+
+        let sections = if settings.sections.is_empty() {
+            vec![default_section(
+                &tops,
+                report.original_centroid,
+                report.centroid_shift,
+                settings.sample_spacing,
+            )]
+        } else {
+            settings.sections.clone()
+        };
+        for section in sections.iter() {
+            report
+                .sections
+                .push(profile(section, &tops, floor, settings));
+        }
+        report
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Every profile point as a CSV row, empty heights are left blank
+    pub fn to_csv(&self) -> String {
+        let mut csv = "section,distance,x,z,height,original_height\n".to_string();
+        let height = |height: Option<f32>| height.map_or(String::new(), |h| h.to_string());
+        for (i, section) in self.sections.iter().enumerate() {
+            for point in section.points.iter() {
+                writeln!(
+                    csv,
+                    "{},{},{},{},{},{}",
+                    i,
+                    point.distance,
+                    point.position.x,
+                    point.position.z,
+                    height(point.height),
+                    height(point.original_height)
+                )
+                .unwrap();
+            }
+        }
+        csv
+    }
+}
+
+// Through the bench along the way the muck moved, or along x if it barely moved, long enough to
+// cover every block up to the sample limit. Blocks thrown past that are flyrock, not muck
+fn default_section(
+    tops: &[BlockTop],
+    centroid: Vec3,
+    shift: Vec3,
+    sample_spacing: f32,
+) -> MuckSection {
+    let direction = shift.xz().try_normalize().unwrap_or(Vec2::X);
+    let max_reach = (MAX_SECTION_SAMPLES - 1) as f32 * sample_spacing / 2.0;
+    let reach = tops
+        .iter()
+        .flat_map(|top| [top.position, top.original_position])
+        .map(|position| (position.xz() - centroid.xz()).dot(direction).abs())
+        .fold(0.0, f32::max)
+        .min(max_reach);
+    let start = centroid.xz() - direction * reach;
+    let end = centroid.xz() + direction * reach;
+    MuckSection {
+        start: Vec3::new(start.x, 0.0, start.y),
+        end: Vec3::new(end.x, 0.0, end.y),
+    }
+}
+
+// Highest block top before and after the blast at every sample along the section
+fn profile(
+    section: &MuckSection,
+    tops: &[BlockTop],
+    floor: f32,
+    settings: &MuckpileSettings,
+) -> SectionProfile {
+    let start = section.start.xz();
+    let along = section.end.xz() - start;
+    let length = along.length();
+    let direction = along / length;
+    let samples = (length / settings.sample_spacing).floor() as usize + 1;
+    let mut heights: Vec<(Option<f32>, Option<f32>)> = vec![(None, None); samples];
+    // the sample a plan position falls in, if it is within the section band
+    let sample = |position: Vec3| {
+        let offset = position.xz() - start;
+        let distance = offset.dot(direction);
+        if offset.perp_dot(direction).abs() > settings.section_width || distance < 0.0 {
+            return None;
+        }
+        let index = (distance / settings.sample_spacing).round() as usize;
+        (index < samples).then_some(index)
+    };
+    for top in tops.iter() {
+        if let Some(index) = sample(top.position) {
+            let height = &mut heights[index].0;
+            *height = Some(height.map_or(top.top, |h| h.max(top.top)));
+        }
+        if let Some(index) = sample(top.original_position) {
+            let height = &mut heights[index].1;
+            *height = Some(height.map_or(top.original_top, |h| h.max(top.original_top)));
+        }
+    }
+    let points = heights
+        .into_iter()
+        .enumerate()
+        .map(|(i, (height, original_height))| {
+            let distance = i as f32 * settings.sample_spacing;
+            let plan = start + direction * distance;
+            ProfilePoint {
+                distance,
+                position: Vec3::new(plan.x, floor, plan.y),
+                height,
+                original_height,
+            }
+        })
+        .collect();
+    SectionProfile {
+        start: section.start,
+        end: section.end,
+        points,
+    }
+}
+
+// Measure the muckpile when the fragmentation report says the muck has settled, and forget it when
+// the report is cleared on reset
+pub fn muckpile_system(
+    fragmentation: Res<FragmentationReport>,
+    scenario: Res<BlastScenario>,
+    mut report: ResMut<MuckpileReport>,
+    blocks: Query<(&Block, &Transform)>,
+) {
+    if !fragmentation.is_changed() {
+        return;
+    }
+    let Some(time) = fragmentation.time else {
+        if report.time.is_some() {
+            *report = MuckpileReport::default();
+        }
+        return;
+    };
+    *report = MuckpileReport::build(
+        blocks
+            .iter()
+            .map(|(block, transform)| (block, transform.translation)),
+        &scenario.muckpile,
+    );
+    report.time = Some(time);
+    info!(
+        "Muckpile: max throw {:.2} m, heave {:.2} m, swell factor {:.2}",
+        report.max_throw, report.heave, report.swell_factor
+    );
+}
+
+// Draw every measured section, the bench before the blast in grey and the muck in gold
+pub fn draw_muckpile_sections(mut gizmos: Gizmos, report: Res<MuckpileReport>) {
+    for section in report.sections.iter() {
+        // samples without blocks are drawn on the floor
+        let at = |point: &ProfilePoint, height: Option<f32>| {
+            Vec3::new(
+                point.position.x,
+                height.unwrap_or(point.position.y),
+                point.position.z,
+            )
+        };
+        gizmos.linestrip(
+            section
+                .points
+                .iter()
+                .map(|point| at(point, point.original_height)),
+            LIGHT_GRAY,
+        );
+        gizmos.linestrip(
+            section.points.iter().map(|point| at(point, point.height)),
+            GOLD,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(x: f32) -> Block {
+        Block {
+            origin: UVec3::ZERO,
+            original_position: Vec3::new(x, 0.5, 0.0),
+            size: Vec3::ONE,
+            mass: 1.0,
+            lithology: 0,
+            grade: 0.0,
+            displaced_by: None,
+            max_impulse: 0.0,
+            peak_speed: 0.0,
+        }
+    }
+
+    // two 1 m cubes side by side on the floor, the second thrown 3 m along x onto a 1 m high ledge
+    fn thrown() -> [(Block, Vec3); 2] {
+        [
+            (block(0.0), Vec3::new(0.0, 0.5, 0.0)),
+            (block(1.0), Vec3::new(4.0, 1.5, 0.0)),
+        ]
+    }
+
+    fn build(blocks: &[(Block, Vec3)], settings: &MuckpileSettings) -> MuckpileReport {
+        MuckpileReport::build(
+            blocks
+                .iter()
+                .map(|(block, translation)| (block, *translation)),
+            settings,
+        )
+    }
+
+    #[test]
+    fn throw_heave_and_centroid() {
+        let report = build(&thrown(), &MuckpileSettings::default());
+        assert_eq!(report.max_throw, 3.0);
+        assert_eq!(report.max_throw_position, Vec3::new(4.0, 1.5, 0.0));
+        assert_eq!(report.heave, 1.0);
+        assert_eq!(report.original_centroid, Vec3::new(0.5, 0.5, 0.0));
+        assert_eq!(report.displaced_centroid, Vec3::new(2.0, 1.0, 0.0));
+        assert_eq!(report.centroid_shift, Vec3::new(1.5, 0.5, 0.0));
+    }
+
+    #[test]
+    fn swell_covers_the_whole_footprint() {
+        // 2 m³ under the bench, 1 m³ under the first block and 2 m³ under the raised one
+        let settings = MuckpileSettings::default();
+        let report = build(&thrown(), &settings);
+        assert!((report.swell_factor - 1.5).abs() < 1e-5);
+
+        // stacked, the top surface covers half the plan area at twice the height
+        let stacked = [
+            (block(0.0), Vec3::new(0.0, 0.5, 0.0)),
+            (block(1.0), Vec3::new(0.0, 1.5, 0.0)),
+        ];
+        let report = build(&stacked, &settings);
+        assert!((report.swell_factor - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn footprints_cover_cell_centres() {
+        let cells = |center: Vec2, footprint: Vec2| {
+            footprint_cells(center, footprint, 0.5).collect::<Vec<_>>()
+        };
+        // a 1 m square centred on the origin covers the four cells around it
+        assert_eq!(
+            cells(Vec2::ZERO, Vec2::ONE),
+            [
+                IVec2::new(-1, -1),
+                IVec2::new(0, -1),
+                IVec2::new(-1, 0),
+                IVec2::new(0, 0)
+            ]
+        );
+        // smaller than a cell, only the one it sits in
+        assert_eq!(cells(Vec2::new(0.1, 0.1), Vec2::splat(0.2)), [IVec2::ZERO]);
+    }
+
+    #[test]
+    fn default_section_follows_the_throw() {
+        let report = build(&thrown(), &MuckpileSettings::default());
+        // along x through the original centroid, far enough to reach the thrown block
+        let section = &report.sections[0];
+        assert_eq!(section.start, Vec3::new(-3.0, 0.0, 0.0));
+        assert_eq!(section.end, Vec3::new(4.0, 0.0, 0.0));
+        assert_eq!(section.points.len(), 15);
+        let heights = |i: usize| (section.points[i].height, section.points[i].original_height);
+        assert_eq!(heights(0), (None, None));
+        assert_eq!(heights(6), (Some(1.0), Some(1.0)));
+        assert_eq!(heights(8), (None, Some(1.0)));
+        assert_eq!(heights(14), (Some(2.0), None));
+        assert_eq!(section.points[14].position, Vec3::new(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn given_sections_take_blocks_within_the_band() {
+        let settings = MuckpileSettings {
+            sections: vec![MuckSection {
+                start: Vec3::new(0.0, 0.0, -2.0),
+                end: Vec3::new(0.0, 0.0, 2.0),
+            }],
+            section_width: 0.5,
+            sample_spacing: 1.0,
+        };
+        let report = build(&thrown(), &settings);
+        let points = &report.sections[0].points;
+        assert_eq!(points.len(), 5);
+        // only the block left at x = 0 is within half a metre of the line
+        assert_eq!(points[2].height, Some(1.0));
+        assert_eq!(points[2].original_height, Some(1.0));
+        assert!(points
+            .iter()
+            .enumerate()
+            .all(|(i, point)| i == 2 || point.height.is_none() && point.original_height.is_none()));
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        assert!(MuckpileSettings::default().validate().is_ok());
+        let section = |start: Vec3, end: Vec3| MuckpileSettings {
+            sections: vec![MuckSection { start, end }],
+            ..default()
+        };
+        assert!(section(Vec3::ZERO, Vec3::X).validate().is_ok());
+        // a vertical line has no length in plan
+        assert!(section(Vec3::ZERO, Vec3::Y).validate().is_err());
+        assert!(section(Vec3::ZERO, Vec3::X * f32::INFINITY)
+            .validate()
+            .is_err());
+        // 0.5 m samples, 9 999 fit and 10 001 don't
+        assert!(section(Vec3::ZERO, Vec3::X * 4999.0).validate().is_ok());
+        assert!(section(Vec3::ZERO, Vec3::X * 5000.0).validate().is_err());
+        let settings = MuckpileSettings {
+            section_width: 0.0,
+            ..default()
+        };
+        assert!(settings.validate().is_err());
+        let settings = MuckpileSettings {
+            sample_spacing: f32::NAN,
+            ..default()
+        };
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn default_section_samples_are_bounded() {
+        // a block thrown a thousand kilometres doesn't stretch the section with it
+        let blocks = [
+            (block(0.0), Vec3::new(0.0, 0.5, 0.0)),
+            (block(1.0), Vec3::new(1e6, 0.5, 0.0)),
+        ];
+        let report = build(&blocks, &MuckpileSettings::default());
+        assert!(report.sections[0].points.len() <= MAX_SECTION_SAMPLES);
+        assert_eq!(report.max_throw, 1e6 - 1.0);
+    }
+}
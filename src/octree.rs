@@ -0,0 +1,243 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Upper bound on the voxels of the fine grid when blocks are merged, the merged block count is
+/// what gets spawned but the grid itself still has to fit in memory
+pub const MAX_ADAPTIVE_VOXELS: usize = 4_000_000;
+
+fn default_levels() -> u32 {
+    2
+}
+
+fn default_detail_distance() -> f32 {
+    2.0
+}
+
+/// Merge voxels into larger blocks away from where the blast happens
+///
+/// `bench_resolution` becomes the size of the smallest blocks, which are kept within
+/// `near_hole_distance` of a drill hole and `near_face_distance` of a free face. Further away up to
+/// 2^`levels` voxels per side are merged into one block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdaptiveResolution {
+    #[serde(default = "default_levels")]
+    pub levels: u32,
+    #[serde(default = "default_detail_distance")]
+    pub near_hole_distance: f32,
+    #[serde(default = "default_detail_distance")]
+    pub near_face_distance: f32,
+}
+
+impl AdaptiveResolution {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.levels > 5 {
+            return Err(format!("levels must be at most 5, got {}", self.levels));
+        }
+        if !(self.near_hole_distance >= 0.0) {
+            return Err(format!(
+                "near_hole_distance must not be negative, got {}",
+                self.near_hole_distance
+            ));
+        }
+        if !(self.near_face_distance >= 0.0) {
+            return Err(format!(
+                "near_face_distance must not be negative, got {}",
+                self.near_face_distance
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A cube of `size` voxels per side starting at voxel `min`, spawned as one block
+#[derive(Debug, Clone, Copy)]
+pub struct OctreeLeaf {
+    pub min: UVec3,
+    pub size: u32,
+}
+
+/// Voxel grid of the bench, x major like setup_bench's loops
+pub struct VoxelGrid {
+    pub counts: UVec3,
+    pub occupied: Vec<bool>,
+}
+
+impl VoxelGrid {
+    pub fn index(&self, voxel: UVec3) -> usize {
+        ((voxel.x * self.counts.y + voxel.y) * self.counts.z + voxel.z) as usize
+    }
+
+    pub fn is_occupied(&self, voxel: UVec3) -> bool {
+        voxel.cmplt(self.counts).all() && self.occupied[self.index(voxel)]
+    }
+
+    /// Steps from every voxel to the nearest empty voxel, walking face to face. Anything outside
+    /// the grid counts as empty apart from below it, where the bench stands on the ground
+    pub fn distance_to_empty(&self) -> Vec<u32> {
+        let mut distance = vec![u32::MAX; self.occupied.len()];
+        let mut queue = VecDeque::new();
+        for x in 0..self.counts.x {
+            for y in 0..self.counts.y {
+                for z in 0..self.counts.z {
+                    let voxel = UVec3::new(x, y, z);
+                    let index = self.index(voxel);
+                    let on_edge = x == 0
+                        || z == 0
+                        || x + 1 == self.counts.x
+                        || y + 1 == self.counts.y
+                        || z + 1 == self.counts.z;
+                    if !self.occupied[index] {
+                        distance[index] = 0;
+                        queue.push_back(voxel);
+                    } else if on_edge {
+                        distance[index] = 1;
+                        queue.push_back(voxel);
+                    }
+                }
+            }
+        }
+        let steps = [
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ];
+        while let Some(voxel) = queue.pop_front() {
+            let next_distance = distance[self.index(voxel)] + 1;
+            for step in steps {
+                let neighbour = voxel.as_ivec3() + step;
+                if neighbour.cmplt(IVec3::ZERO).any() {
+                    continue;
+                }
+                let neighbour = neighbour.as_uvec3();
+                if !neighbour.cmplt(self.counts).all() {
+                    continue;
+                }
+                let index = self.index(neighbour);
+                if distance[index] > next_distance {
+                    distance[index] = next_distance;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        distance
+    }
+
+    // every voxel of the cube is part of the bench
+    fn is_full(&self, min: UVec3, size: u32) -> bool {
+        for x in min.x..min.x + size {
+            for y in min.y..min.y + size {
+                for z in min.z..min.z + size {
+                    if !self.is_occupied(UVec3::new(x, y, z)) {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Cover the occupied voxels with cubes of up to 2^`levels` voxels per side, splitting every
+    /// cube `needs_detail` asks for down to single voxels
+    pub fn build_octree(
+        &self,
+        levels: u32,
+        needs_detail: impl Fn(UVec3, u32) -> bool,
+    ) -> Vec<OctreeLeaf> {
+        let root_size = 1 << levels;
+        let mut leaves = Vec::new();
+        let mut stack = Vec::new();
+        for x in (0..self.counts.x).step_by(root_size as usize) {
+            for y in (0..self.counts.y).step_by(root_size as usize) {
+                for z in (0..self.counts.z).step_by(root_size as usize) {
+                    stack.push(OctreeLeaf {
+                        min: UVec3::new(x, y, z),
+                        size: root_size,
+                    });
+                }
+            }
+        }
+        while let Some(cell) = stack.pop() {
+            if cell.size == 1 {
+                if self.is_occupied(cell.min) {
+                    leaves.push(cell);
+                }
+                continue;
+            }
+            if self.is_full(cell.min, cell.size) && !needs_detail(cell.min, cell.size) {
+                leaves.push(cell);
+                continue;
+            }
+            let half = cell.size / 2;
+            for x in [0, half] {
+                for y in [0, half] {
+                    for z in [0, half] {
+                        let min = cell.min + UVec3::new(x, y, z);
+                        if min.cmplt(self.counts).all() {
+                            stack.push(OctreeLeaf { min, size: half });
+                        }
+                    }
+                }
+            }
+        }
+        leaves
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_grid(n: u32) -> VoxelGrid {
+        VoxelGrid {
+            counts: UVec3::splat(n),
+            occupied: vec![true; (n * n * n) as usize],
+        }
+    }
+
+    fn voxel_count(leaves: &[OctreeLeaf]) -> u32 {
+        leaves.iter().map(|leaf| leaf.size.pow(3)).sum()
+    }
+
+    #[test]
+    fn full_grid_merges_into_one_block() {
+        let leaves = full_grid(4).build_octree(2, |_, _| false);
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].size, 4);
+        assert_eq!(leaves[0].min, UVec3::ZERO);
+    }
+
+    #[test]
+    fn detail_splits_down_to_voxels() {
+        let leaves = full_grid(4).build_octree(2, |_, _| true);
+        assert_eq!(leaves.len(), 64);
+        assert!(leaves.iter().all(|leaf| leaf.size == 1));
+    }
+
+    #[test]
+    fn partial_cubes_are_split() {
+        // the 2 x 2 x 2 corner merges, the other 19 voxels of the 3 x 3 x 3 grid stay single
+        let leaves = full_grid(3).build_octree(1, |_, _| false);
+        assert_eq!(leaves.len(), 20);
+        assert_eq!(voxel_count(&leaves), 27);
+
+        let mut grid = full_grid(2);
+        let index = grid.index(UVec3::new(1, 1, 1));
+        grid.occupied[index] = false;
+        let leaves = grid.build_octree(1, |_, _| false);
+        assert_eq!(leaves.len(), 7);
+    }
+
+    #[test]
+    fn distance_to_the_free_faces() {
+        let grid = full_grid(3);
+        let distance = grid.distance_to_empty();
+        assert_eq!(distance[grid.index(UVec3::new(0, 1, 1))], 1);
+        assert_eq!(distance[grid.index(UVec3::new(1, 1, 1))], 2);
+        // the ground under the bench is not a free face
+        assert_eq!(distance[grid.index(UVec3::new(1, 0, 1))], 2);
+    }
+}
@@ -0,0 +1,570 @@
+use crate::airblast::AirblastSettings;
+use crate::bench_geometry::BenchGeometry;
+use crate::blast_pattern::BlastPattern;
+use crate::blast_report::Monitor;
+use crate::bonds::BondSettings;
+use crate::explosives::*;
+use crate::flyrock::FlyrockSettings;
+use crate::joints::*;
+use crate::kuz_ram::KuzRamSettings;
+use crate::lithology::*;
+use crate::muckpile::MuckpileSettings;
+use crate::octree::*;
+use crate::useful_structs::*;
+use crate::vibration::VibrationSettings;
+use crate::voronoi::*;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::TimestepMode;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Upper bound on the number of blocks a scenario may spawn, keeps a typo in the
+/// resolution from freezing the page
+pub const MAX_BENCH_BLOCKS: usize = 200_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+#[serde(default)]
+pub struct BlastScenario {
+    // everything needed to build and fire a bench, loaded from JSON or RON
+    pub bench_dimensions: [f32; 3], // x, y, z dimensions of the bench
+    pub bench_resolution: [f32; 3], // size of each cube along x, y, z
+    pub bench_position: [f32; 3],   // position of the first cube of the bench
+    pub adaptive: Option<AdaptiveResolution>, // merge blocks far from the holes, bench_resolution is then the smallest block
+    pub voronoi: Option<VoronoiSettings>, // irregular fragments instead of cubes, bench_resolution still sets the bench outline
+    pub geometry: Option<BenchGeometry>,  // replaces the box above, e.g. with a surveyed face
+    pub drill_holes: DrillHoles,
+    pub pattern: Option<BlastPattern>, // generated holes are added to drill_holes when loaded
+    pub explosives: Vec<ExplosiveProduct>, // added to the built-in ExplosiveLibrary presets
+    pub impulse_constant: f32, // site constant K of the blast impulse law, see explosives::blast_impulse
+    pub attenuation: f32, // site constant beta, how fast the impulse decays with scaled distance
+    pub kuz_ram: KuzRamSettings, // rock factor and pattern for the empirical fragmentation prediction
+    pub muckpile: MuckpileSettings, // sections the muckpile profile is measured along
+    pub flyrock: FlyrockSettings, // when a block counts as flyrock
+    pub monitors: Vec<Monitor>,  // points around the blast vibration and airblast are predicted at
+    pub vibration: VibrationSettings, // site law for the ground vibration at the monitors
+    pub airblast: AirblastSettings, // site law and confinement for the airblast at the monitors
+    pub lithologies: Vec<Lithology>, // the first is the host rock, a plain red rock if empty
+    pub domains: Vec<LithologyDomain>, // where the other lithologies are, later ones win
+    pub joint_sets: Vec<JointSet>, // structure of the rock mass, blocks on a joint slide and break easier
+    pub bonds: BondSettings,
+    pub carve_drill_holes: bool, // leave out the blocks the drilled holes pass through
+    pub show_charge_columns: bool, // spawn a mesh for every charge, stemming and air interval
+    pub seed: Option<u64>, // replay the exact same blast every run, physics then steps by `timestep`
+    pub timestep: f32,     // seconds per physics step of a seeded run
+}
+
+impl Default for BlastScenario {
+    fn default() -> Self {
+        BlastScenario {
+            bench_dimensions: [20.0, 4.0, 10.0],
+            bench_resolution: [0.5, 0.5, 0.5],
+            bench_position: [0.0, 0.1, 0.0],
+            adaptive: None,
+            voronoi: None,
+            geometry: None,
+            pattern: None,
+            drill_holes: DrillHoles::new(vec![
+                DrillHole::new(Vec3::new(6.0, 1.8, 5.0), 1.0, 3.6, 0.0),
+                DrillHole::new(Vec3::new(14.0, 1.8, 5.0), 1.0, 3.6, 42.0),
+            ]),
+            explosives: Vec::new(),
+            impulse_constant: 250.0,
+            attenuation: 1.6,
+            kuz_ram: KuzRamSettings::default(),
+            muckpile: MuckpileSettings::default(),
+            flyrock: FlyrockSettings::default(),
+            monitors: Vec::new(),
+            vibration: VibrationSettings::default(),
+            airblast: AirblastSettings::default(),
+            lithologies: Vec::new(),
+            domains: Vec::new(),
+            joint_sets: Vec::new(),
+            bonds: BondSettings::default(),
+            carve_drill_holes: false,
+            show_charge_columns: false,
+            seed: None,
+            timestep: 1.0 / 60.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    /// The file could not be read
+    Io(String),
+    /// The text is not valid JSON / RON for a scenario
+    Parse(String),
+    /// The scenario parsed but describes something we cannot simulate
+    Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(msg) => write!(f, "could not read scenario: {}", msg),
+            ScenarioError::Parse(msg) => write!(f, "could not parse scenario: {}", msg),
+            ScenarioError::Invalid(msg) => write!(f, "invalid scenario: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl BlastScenario {
+    pub fn from_json(json: &str) -> Result<Self, ScenarioError> {
+        Self::parse_json(json)?.finish()
+    }
+
+    pub fn from_ron(ron: &str) -> Result<Self, ScenarioError> {
+        Self::parse_ron(ron)?.finish()
+    }
+
+    // parse without finishing, for loaders that still fill in parts of the scenario
+    fn parse_json(json: &str) -> Result<Self, ScenarioError> {
+        serde_json::from_str(json).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    fn parse_ron(ron: &str) -> Result<Self, ScenarioError> {
+        ron::from_str(ron).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    /// Validate the scenario and fill in anything left implicit, the loaders do this after parsing
+    /// and scenarios built in code should go through it too
+    pub fn finish(mut self) -> Result<Self, ScenarioError> {
+        // expand the pattern into plain holes so saving the scenario doesn't drill them twice
+        if let Some(pattern) = self.pattern.take() {
+            // the empirical fragmentation models want the designed burden and spacing
+            self.kuz_ram.burden.get_or_insert(pattern.burden);
+            self.kuz_ram.spacing.get_or_insert(pattern.spacing);
+            let generated = pattern
+                .generate()
+                .map_err(|e| ScenarioError::Invalid(format!("pattern: {}", e)))?;
+            self.drill_holes.0.extend(generated);
+        }
+        self.validate()?;
+        self.drill_holes.assign_missing_ids();
+        Ok(self)
+    }
+
+    /// Load a scenario file, `.ron` files are read as RON and everything else as JSON
+    ///
+    /// `library` holds products from a separate explosive library file, the scenario's own
+    /// `explosives` take precedence over them
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_path(path: &str, library: &[ExplosiveProduct]) -> Result<Self, ScenarioError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ScenarioError::Io(format!("{}: {}", path, e)))?;
+        let mut scenario = if path.ends_with(".ron") {
+            Self::parse_ron(&text)?
+        } else {
+            Self::parse_json(&text)?
+        };
+        scenario.explosives.splice(0..0, library.iter().cloned());
+        // survey files are found next to the scenario
+        if let Some(BenchGeometry::Import(import)) = &scenario.geometry {
+            let base_dir = std::path::Path::new(path)
+                .parent()
+                .unwrap_or(std::path::Path::new(""));
+            let surface = import.read_path(base_dir).map_err(ScenarioError::Io)?;
+            scenario.geometry = Some(BenchGeometry::Surface(surface));
+        }
+        scenario.finish()
+    }
+
+    // the built-in presets with the scenario's own products on top
+    pub fn explosive_library(&self) -> ExplosiveLibrary {
+        let mut library = ExplosiveLibrary::default();
+        for product in self.explosives.iter() {
+            library.insert(product.clone());
+        }
+        library
+    }
+
+    // the geometry if given, the box of bench_dimensions and bench_position otherwise
+    pub fn bench_geometry(&self) -> BenchGeometry {
+        self.geometry.clone().unwrap_or(BenchGeometry::Box {
+            dimensions: self.bench_dimensions,
+            position: self.bench_position,
+        })
+    }
+
+    pub fn rock_mass(&self) -> RockMass {
+        RockMass::new(
+            self.joint_sets.clone(),
+            self.lithologies.clone(),
+            self.domains.clone(),
+        )
+    }
+
+    // seeded runs step the physics by a fixed amount every frame so they don't depend on the frame
+    // rate, otherwise rapier follows the frame time like before
+    pub fn timestep_mode(&self) -> TimestepMode {
+        match self.seed {
+            Some(_) => TimestepMode::Fixed {
+                dt: self.timestep,
+                substeps: 1,
+            },
+            None => TimestepMode::Variable {
+                max_dt: 1.0 / 60.0,
+                time_scale: 1.0,
+                substeps: 1,
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // check the numbers make sense before we try to spawn anything
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let axes = ["x", "y", "z"];
+        for i in 0..3 {
+            let dim = self.bench_dimensions[i];
+            let res = self.bench_resolution[i];
+            if !(dim.is_finite() && dim > 0.0) {
+                return Err(ScenarioError::Invalid(format!(
+                    "bench_dimensions.{} must be positive, got {}",
+                    axes[i], dim
+                )));
+            }
+            if !(res.is_finite() && res > 0.0) {
+                return Err(ScenarioError::Invalid(format!(
+                    "bench_resolution.{} must be positive, got {}",
+                    axes[i], res
+                )));
+            }
+            if res > dim {
+                return Err(ScenarioError::Invalid(format!(
+                    "bench_resolution.{} ({}) is larger than the bench ({})",
+                    axes[i], res, dim
+                )));
+            }
+            if !self.bench_position[i].is_finite() {
+                return Err(ScenarioError::Invalid(format!(
+                    "bench_position.{} must be a finite number",
+                    axes[i]
+                )));
+            }
+        }
+
+        let geometry = self.bench_geometry();
+        geometry
+            .validate()
+            .map_err(|e| ScenarioError::Invalid(format!("geometry: {}", e)))?;
+        let (position, counts) = geometry.grid(&self.bench_resolution);
+        // a grid too big to count is over every limit
+        let block_count = counts
+            .iter()
+            .try_fold(1usize, |count, &n| count.checked_mul(n))
+            .unwrap_or(usize::MAX);
+        match &self.adaptive {
+            // how far blocks merge depends on the holes, which can still change before the bench is
+            // built, so setup_bench checks the real block count. Here the voxel grid is bounded, and
+            // so is the fewest blocks it could merge into
+            Some(adaptive) => {
+                adaptive
+                    .validate()
+                    .map_err(|e| ScenarioError::Invalid(format!("adaptive: {}", e)))?;
+                if block_count > MAX_ADAPTIVE_VOXELS {
+                    return Err(ScenarioError::Invalid(format!(
+                        "bench would need {} voxels, the limit is {}",
+                        block_count, MAX_ADAPTIVE_VOXELS
+                    )));
+                }
+                let root_size = 1 << adaptive.levels;
+                let merged_count: usize = counts.iter().map(|n| n.div_ceil(root_size)).product();
+                if merged_count > MAX_BENCH_BLOCKS {
+                    return Err(ScenarioError::Invalid(format!(
+                        "bench would spawn at least {} blocks even fully merged, the limit is {}",
+                        merged_count, MAX_BENCH_BLOCKS
+                    )));
+                }
+            }
+            None => {
+                if block_count > MAX_BENCH_BLOCKS && self.voronoi.is_none() {
+                    return Err(ScenarioError::Invalid(format!(
+                        "bench would spawn {} blocks, the limit is {}",
+                        block_count, MAX_BENCH_BLOCKS
+                    )));
+                }
+            }
+        }
+        if let Some(voronoi) = &self.voronoi {
+            voronoi
+                .validate()
+                .map_err(|e| ScenarioError::Invalid(format!("voronoi: {}", e)))?;
+            if self.adaptive.is_some() {
+                return Err(ScenarioError::Invalid(
+                    "adaptive merges cube blocks, it can't be combined with voronoi".to_string(),
+                ));
+            }
+            // the box around the bench setup_bench seeds, with the extra seeds around the holes.
+            // The holes can still change, setup_bench counts them again before scattering
+            let resolution = Vec3::from(self.bench_resolution);
+            let min = position - resolution / 2.0;
+            let max =
+                min + Vec3::new(counts[0] as f32, counts[1] as f32, counts[2] as f32) * resolution;
+            // the cells alone are counted first so a huge grid fails before it is walked
+            let cell_count = (max - min) / voronoi.cell_size;
+            if cell_count.ceil().max(Vec3::ONE).element_product() > MAX_BENCH_BLOCKS as f32 {
+                return Err(ScenarioError::Invalid(format!(
+                    "voronoi would seed more than {} cells",
+                    MAX_BENCH_BLOCKS
+                )));
+            }
+            let seed_count = voronoi.seed_count(min, max, &self.drill_holes);
+            if seed_count > MAX_BENCH_BLOCKS {
+                return Err(ScenarioError::Invalid(format!(
+                    "voronoi would seed {} cells, the limit is {}",
+                    seed_count, MAX_BENCH_BLOCKS
+                )));
+            }
+        }
+
+        for product in self.explosives.iter() {
+            product
+                .validate()
+                .map_err(|e| ScenarioError::Invalid(format!("explosives: {}", e)))?;
+        }
+        for (i, lithology) in self.lithologies.iter().enumerate() {
+            lithology
+                .validate()
+                .map_err(|e| ScenarioError::Invalid(format!("lithologies[{}]: {}", i, e)))?;
+            if self.lithologies[..i]
+                .iter()
+                .any(|other| other.name == lithology.name)
+            {
+                return Err(ScenarioError::Invalid(format!(
+                    "lithologies[{}]: name '{}' is used more than once",
+                    i, lithology.name
+                )));
+            }
+        }
+        for (i, domain) in self.domains.iter().enumerate() {
+            domain
+                .validate()
+                .map_err(|e| ScenarioError::Invalid(format!("domains[{}]: {}", i, e)))?;
+            if !self
+                .lithologies
+                .iter()
+                .any(|lithology| lithology.name == domain.lithology())
+            {
+                return Err(ScenarioError::Invalid(format!(
+                    "domains[{}]: unknown lithology '{}'",
+                    i,
+                    domain.lithology()
+                )));
+            }
+        }
+        for (i, joint_set) in self.joint_sets.iter().enumerate() {
+            joint_set
+                .validate()
+                .map_err(|e| ScenarioError::Invalid(format!("joint_sets[{}]: {}", i, e)))?;
+        }
+
+        self.bonds
+            .validate()
+            .map_err(|e| ScenarioError::Invalid(format!("bonds: {}", e)))?;
+        self.kuz_ram
+            .validate()
+            .map_err(|e| ScenarioError::Invalid(format!("kuz_ram: {}", e)))?;
+        self.muckpile
+            .validate()
+            .map_err(|e| ScenarioError::Invalid(format!("muckpile: {}", e)))?;
+        self.flyrock
+            .validate()
+            .map_err(|e| ScenarioError::Invalid(format!("flyrock: {}", e)))?;
+        for (i, monitor) in self.monitors.iter().enumerate() {
+            monitor
+                .validate()
+                .map_err(|e| ScenarioError::Invalid(format!("monitors[{}]: {}", i, e)))?;
+        }
+        self.vibration
+            .validate()
+            .map_err(|e| ScenarioError::Invalid(format!("vibration: {}", e)))?;
+        self.airblast
+            .validate()
+            .map_err(|e| ScenarioError::Invalid(format!("airblast: {}", e)))?;
+
+        if !(self.timestep > 0.0 && self.timestep <= 0.1) {
+            return Err(ScenarioError::Invalid(format!(
+                "timestep must be between 0 and 0.1 s, got {}",
+                self.timestep
+            )));
+        }
+
+        let library = self.explosive_library();
+
+        for (i, drill_hole) in self.drill_holes.0.iter().enumerate() {
+            drill_hole
+                .validate_with(&library)
+                .map_err(|e| ScenarioError::Invalid(format!("drill_holes[{}]: {}", i, e)))?;
+            if drill_hole.id != 0
+                && self.drill_holes.0[..i]
+                    .iter()
+                    .any(|other| other.id == drill_hole.id)
+            {
+                return Err(ScenarioError::Invalid(format!(
+                    "drill_holes[{}]: id {} is used by more than one hole",
+                    i, drill_hole.id
+                )));
+            }
+        }
+
+        if !(self.impulse_constant.is_finite() && self.impulse_constant >= 0.0) {
+            return Err(ScenarioError::Invalid(format!(
+                "impulse_constant must not be negative, got {}",
+                self.impulse_constant
+            )));
+        }
+        if !(self.attenuation.is_finite() && self.attenuation > 0.0) {
+            return Err(ScenarioError::Invalid(format!(
+                "attenuation must be positive, got {}",
+                self.attenuation
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(scenario: BlastScenario) -> bool {
+        matches!(scenario.validate(), Err(ScenarioError::Invalid(_)))
+    }
+
+    #[test]
+    fn the_default_scenario_is_valid() {
+        let scenario = BlastScenario::default().finish().unwrap();
+        let ids: Vec<u32> = scenario.drill_holes.0.iter().map(|hole| hole.id).collect();
+        assert_eq!(ids, [1, 2]);
+    }
+
+    #[test]
+    fn bench_sizes_are_checked() {
+        assert!(invalid(BlastScenario {
+            bench_dimensions: [20.0, f32::NAN, 10.0],
+            ..default()
+        }));
+        assert!(invalid(BlastScenario {
+            bench_resolution: [0.5, 5.0, 0.5],
+            ..default()
+        }));
+        // a billion blocks
+        assert!(invalid(BlastScenario {
+            bench_dimensions: [100.0, 100.0, 100.0],
+            bench_resolution: [0.1, 0.1, 0.1],
+            ..default()
+        }));
+    }
+
+    #[test]
+    fn block_count_does_not_overflow() {
+        // every axis has more voxels than fit in a usize, the product must not wrap to something
+        // small
+        let scenario = BlastScenario {
+            bench_dimensions: [1e30, 1e30, 1e30],
+            bench_resolution: [1.0, 1.0, 1.0],
+            ..default()
+        };
+        assert!(invalid(scenario));
+    }
+
+    #[test]
+    fn duplicate_hole_ids_are_rejected() {
+        let mut scenario = BlastScenario::default();
+        scenario.drill_holes.0[1].id = scenario.drill_holes.0[0].id;
+        assert!(invalid(scenario));
+        // unassigned ids are filled in by finish
+        let mut scenario = BlastScenario::default();
+        for drill_hole in scenario.drill_holes.0.iter_mut() {
+            drill_hole.id = 0;
+        }
+        assert!(scenario.validate().is_ok());
+    }
+
+    #[test]
+    fn references_and_combinations_are_checked() {
+        let ore = Lithology {
+            name: "Ore".to_string(),
+            ..default()
+        };
+        assert!(invalid(BlastScenario {
+            lithologies: vec![ore.clone(), ore.clone()],
+            ..default()
+        }));
+        assert!(invalid(BlastScenario {
+            lithologies: vec![ore],
+            domains: vec![LithologyDomain::Layer {
+                lithology: "Waste".to_string(),
+                bottom: 0.0,
+                top: 1.0,
+            }],
+            ..default()
+        }));
+        assert!(invalid(BlastScenario {
+            adaptive: Some(AdaptiveResolution {
+                levels: 2,
+                near_hole_distance: 2.0,
+                near_face_distance: 1.0,
+            }),
+            voronoi: Some(VoronoiSettings {
+                cell_size: 1.0,
+                near_hole_cell_size: None,
+                near_hole_distance: 2.0,
+            }),
+            ..default()
+        }));
+        assert!(invalid(BlastScenario {
+            timestep: 0.5,
+            ..default()
+        }));
+        assert!(invalid(BlastScenario {
+            attenuation: 0.0,
+            ..default()
+        }));
+    }
+
+    #[test]
+    fn loaders_report_what_went_wrong() {
+        assert!(matches!(
+            BlastScenario::from_json("{"),
+            Err(ScenarioError::Parse(_))
+        ));
+        assert!(matches!(
+            BlastScenario::from_json(r#"{"timestep": -1}"#),
+            Err(ScenarioError::Invalid(_))
+        ));
+        let scenario = BlastScenario::from_ron("(seed: Some(7))").unwrap();
+        assert_eq!(scenario.seed, Some(7));
+        assert_eq!(scenario.drill_holes.0.len(), 2);
+    }
+
+    #[test]
+    fn files_are_read_by_extension() {
+        let dir = std::env::temp_dir();
+        let library = [ExplosiveProduct::new("Site ANFO", 820.0, 3.7, 4000.0)];
+        let ron = dir.join("blast_scenario_test.ron");
+        std::fs::write(&ron, "(seed: Some(3))").unwrap();
+        let scenario = BlastScenario::from_path(ron.to_str().unwrap(), &library).unwrap();
+        assert_eq!(scenario.seed, Some(3));
+        assert!(scenario.explosive_library().get("Site ANFO").is_some());
+
+        // anything else is JSON
+        let json = dir.join("blast_scenario_test.txt");
+        std::fs::write(&json, "(seed: Some(3))").unwrap();
+        assert!(matches!(
+            BlastScenario::from_path(json.to_str().unwrap(), &library),
+            Err(ScenarioError::Parse(_))
+        ));
+        assert!(matches!(
+            BlastScenario::from_path(dir.join("missing.json").to_str().unwrap(), &[]),
+            Err(ScenarioError::Io(_))
+        ));
+        std::fs::remove_file(ron).unwrap();
+        std::fs::remove_file(json).unwrap();
+    }
+}
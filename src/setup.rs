@@ -1,10 +1,21 @@
-use crate::useful_structs::DrillHoles;
+use crate::bench_geometry::BenchGeometry;
+use crate::bonds::*;
+use crate::joints::RockMass;
+use crate::octree::*;
+use crate::scenario::MAX_BENCH_BLOCKS;
+use crate::useful_structs::{Block, DrillHoles};
+use crate::voronoi::*;
 use bevy::prelude::*;
 use bevy::tasks::ComputeTaskPool;
+use bevy::utils::HashMap;
 use bevy_rapier3d::prelude::*;
 use rand::Rng;
 use std::cmp::{max, min};
 
+// marks every block spawned by setup_bench so the bench can be cleared on reset
+#[derive(Component)]
+pub struct BenchBlock;
+
 pub fn setup_ground(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -24,35 +35,82 @@ pub fn setup_ground(
         .insert(Name::new("Ground"));
 }
 
+// check if a block centered on `point` is cut by a drilled hole, measured from the hole axis so
+// angled and sub-drilled holes work. The hole is far thinner than a block so any block the axis
+// passes through is taken out
+fn is_drill_hole(point: Vec3, drill_holes: &DrillHoles, block_size: f32) -> bool {
+    for drill_hole in drill_holes.0.iter() {
+        if drill_hole.distance_to_axis(point) <= drill_hole.diameter / 2.0 + block_size / 2.0 {
+            return true;
+        }
+    }
+    false
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn setup_bench(
     // this function takes in the dimensions of the bench discretizes the polygon into set of smaller cubes and spawns them
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    dimensions: &[f32; 3],    // x, y, z dimensions
+    geometry: &BenchGeometry, // volume of the bench, a box or a surveyed face
     resolution: &[f32; 3],    // resolution of each dimension, basically the size of each cube
-    position: &[f32; 3],      // position of the center of the bench
     drill_holes: &DrillHoles, // position of the center of the drill hole and the radius of the drill hole
+    carve_drill_holes: bool,  // leave out the blocks the drilled holes pass through
+    adaptive: Option<&AdaptiveResolution>, // merge blocks away from the holes and free faces
+    voronoi: Option<&VoronoiSettings>, // irregular fragments instead of the cube grid
+    rock_mass: &RockMass,     // joint sets lowering the friction of the blocks they cut
+    bonds: &BondSettings,     // strength of the bonds glueing neighbouring blocks
+    rng: &mut impl Rng,       // the simulation's random numbers, for placing Voronoi seeds
 ) {
     const COLLIDER_GROUP_RADIUS: usize = 1;
-    let x_dim = dimensions[0];
-    let y_dim = dimensions[1];
-    let z_dim = dimensions[2];
-
     let x_res = resolution[0];
     let y_res = resolution[1];
     let z_res = resolution[2];
 
-    let x_num_slices = (x_dim / x_res) as usize;
-    let y_num_slices = (y_dim / y_res) as usize;
-    let z_num_slices = (z_dim / z_res) as usize;
+    // voxel grid covering the bench, voxels outside its volume are skipped
+    let (position, [x_num_slices, y_num_slices, z_num_slices]) = geometry.grid(resolution);
 
-    let cube_mesh = meshes.add(Cuboid::new(x_res, y_res, z_res));
-    let cube_material = materials.add(StandardMaterial {
-        base_color: Color::srgb(1.0, 0.0, 0.0),
-        ..default()
-    });
+    // one material per lithology, blocks with a jointed face are darker so the structure shows up
+    // in the bench
+    let lithology_materials: Vec<[Handle<StandardMaterial>; 2]> = rock_mass
+        .lithologies
+        .iter()
+        .map(|lithology| {
+            let [r, g, b] = lithology.color;
+            [
+                materials.add(StandardMaterial {
+                    base_color: Color::srgb(r, g, b),
+                    ..default()
+                }),
+                materials.add(StandardMaterial {
+                    base_color: Color::srgb(r * 0.55, g * 0.55, b * 0.55),
+                    ..default()
+                }),
+            ]
+        })
+        .collect();
+    if let Some(voronoi) = voronoi {
+        setup_voronoi_bench(
+            &mut commands,
+            &mut meshes,
+            &lithology_materials,
+            geometry,
+            resolution,
+            drill_holes,
+            carve_drill_holes,
+            voronoi,
+            rock_mass,
+            bonds,
+            rng,
+        );
+        return;
+    }
+
+    let voxel_dimensions = Vec3::new(x_res, y_res, z_res);
+    let voxel_center = |voxel: UVec3| position + voxel.as_vec3() * voxel_dimensions;
     let pool = ComputeTaskPool::get();
+    let block_size = x_res.min(y_res).min(z_res);
 
     // We split the cube columns into groups of multiple columns so that we have 20 groups from the 32 bevy GROUPS
     // let mut bench_column_collider_groups: Vec<((u32, u32), String)> = Vec::new();
@@ -62,43 +120,109 @@ pub fn setup_bench(
 
     // println!("{:?}", bench_column_collider_groups);
 
-    // check if a point is inside a drill hole
-    fn is_drill_hole(x: f32, z: f32, drill_holes: &DrillHoles) -> bool {
-        for drill_hole in drill_holes.0.iter() {
-            let distance =
-                ((x - drill_hole.position.x).powi(2) + (z - drill_hole.position.z).powi(2)).sqrt();
-            if distance <= drill_hole.radius {
-                return true;
+    // mark the voxels that are part of the bench
+    let mut voxels = VoxelGrid {
+        counts: UVec3::new(
+            x_num_slices as u32,
+            y_num_slices as u32,
+            z_num_slices as u32,
+        ),
+        occupied: vec![false; x_num_slices * y_num_slices * z_num_slices],
+    };
+    for x in 0..x_num_slices {
+        // let begin_index = max(0, x/2-COLLIDER_GROUP_RADIUS);
+        // let end_index = min(x_num_slices/2, x/2+COLLIDER_GROUP_RADIUS);
+        // let collider_group: Vec<String> = bench_column_collider_groups[begin_index..=end_index].iter().map(|(_, group_name)| group_name.clone()).collect();
+        // println!("{:?}", collider_group);
+        for y in 0..y_num_slices {
+            for z in 0..z_num_slices {
+                let voxel = UVec3::new(x as u32, y as u32, z as u32);
+                let center = voxel_center(voxel);
+                let occupied = geometry.contains(center)
+                    && !(carve_drill_holes && is_drill_hole(center, drill_holes, block_size));
+                let index = voxels.index(voxel);
+                voxels.occupied[index] = occupied;
             }
         }
-        false
+    }
+
+    // cover the bench with blocks, single voxels unless blocks are merged away from the action
+    let leaves = match adaptive {
+        Some(adaptive) => {
+            let face_distance = voxels.distance_to_empty();
+            voxels.build_octree(adaptive.levels, |min, size| {
+                let dimensions = voxel_dimensions * size as f32;
+                let center = voxel_center(min) + (dimensions - voxel_dimensions) / 2.0;
+                let half_diagonal = dimensions.length() / 2.0;
+                if drill_holes.0.iter().any(|drill_hole| {
+                    drill_hole.distance_to_axis(center) - half_diagonal
+                        < adaptive.near_hole_distance
+                }) {
+                    return true;
+                }
+                // closest voxel of the cell to an empty one, a distance of 1 is on the face
+                let mut closest = u32::MAX;
+                for x in min.x..min.x + size {
+                    for y in min.y..min.y + size {
+                        for z in min.z..min.z + size {
+                            let index = voxels.index(UVec3::new(x, y, z));
+                            closest = closest.min(face_distance[index]);
+                        }
+                    }
+                }
+                (closest.saturating_sub(1)) as f32 * block_size < adaptive.near_face_distance
+            })
+        }
+        None => voxels.build_octree(0, |_, _| false),
+    };
+    // the scenario only bounds the voxel grid, how many blocks stay unmerged depends on the holes
+    if leaves.len() > MAX_BENCH_BLOCKS {
+        error!(
+            "bench would spawn {} blocks, the limit is {}. Nothing was spawned, use a coarser \
+             bench_resolution or smaller adaptive distances",
+            leaves.len(),
+            MAX_BENCH_BLOCKS
+        );
+        return;
+    }
+
+    // one mesh per block size
+    let mut cube_meshes: HashMap<u32, Handle<Mesh>> = HashMap::new();
+    for leaf in leaves.iter() {
+        cube_meshes.entry(leaf.size).or_insert_with(|| {
+            let dimensions = voxel_dimensions * leaf.size as f32;
+            meshes.add(Cuboid::new(dimensions.x, dimensions.y, dimensions.z))
+        });
     }
 
     // assign the regions of the polygon to the drill holes
     // spawn the cubes
     let cube_data: Vec<_> = pool.scope(|s| {
-        for x in 0..x_num_slices {
-            // let begin_index = max(0, x/2-COLLIDER_GROUP_RADIUS);
-            // let end_index = min(x_num_slices/2, x/2+COLLIDER_GROUP_RADIUS);
-            // let collider_group: Vec<String> = bench_column_collider_groups[begin_index..=end_index].iter().map(|(_, group_name)| group_name.clone()).collect();
-            // println!("{:?}", collider_group);
-            for y in 0..y_num_slices {
-                for z in 0..z_num_slices {
-                    let x_pos = position[0] + x as f32 * x_res;
-                    let y_pos = position[1] + y as f32 * y_res;
-                    let z_pos = position[2] + z as f32 * z_res;
-                    // if is_drill_hole(x_pos, z_pos, drill_holes) {
-                    //     continue;
-                    // }
-                    s.spawn(async move {
-                        (
-                            Transform::from_xyz(x_pos, y_pos, z_pos),
-                            Collider::cuboid(x_res / 2.0, y_res / 2.0, z_res / 2.0),
-                            ColliderMassProperties::Mass(rand::random::<f32>() * 10.0), // Random mass between 0 and 10
-                        )
-                    })
-                }
-            }
+        for leaf in leaves.iter() {
+            let dimensions = voxel_dimensions * leaf.size as f32;
+            let center = voxel_center(leaf.min) + (dimensions - voxel_dimensions) / 2.0;
+            let lithology_index = rock_mass.lithology_at(center);
+            let lithology = &rock_mass.lithologies[lithology_index];
+            // the block slides on its weakest face
+            let faces = rock_mass.block_faces(center, dimensions);
+            let friction = faces
+                .iter()
+                .map(|face| face.friction)
+                .fold(lithology.friction, f32::min);
+            let jointed = faces.iter().any(|face| face.strength < 1.0);
+            let restitution = lithology.restitution;
+            let mass = lithology.density * dimensions.x * dimensions.y * dimensions.z;
+            s.spawn(async move {
+                (
+                    *leaf,
+                    Transform::from_translation(center),
+                    Friction::coefficient(friction),
+                    Restitution::coefficient(restitution),
+                    (lithology_index, jointed),
+                    Collider::cuboid(dimensions.x / 2.0, dimensions.y / 2.0, dimensions.z / 2.0),
+                    mass,
+                )
+            })
         }
     });
 
@@ -128,24 +252,259 @@ pub fn setup_bench(
     //     ));
     // }
 
-    // add mesh to the cubesafter adding the colliders
-    let cube_mesh = cube_mesh.clone();
-    let cube_material = cube_material.clone();
-    commands.spawn_batch(
+    // add mesh to the cubesafter adding the colliders, keeping which block owns every voxel so
+    // neighbours can be bonded
+    let mut owners: Vec<Option<(Entity, usize, Vec3)>> = vec![None; voxels.occupied.len()];
+    for (leaf, transform, friction, restitution, (lithology_index, jointed), collider, mass) in
         cube_data
-            .into_iter()
-            .map(move |(transform, collider, mass)| {
-                (
-                    PbrBundle {
-                        mesh: cube_mesh.clone(),
-                        material: cube_material.clone(),
-                        transform,
-                        ..default()
-                    },
-                    RigidBody::Dynamic,
-                    collider,
-                    ColliderMassProperties::Mass(2.0),
-                )
-            }),
+    {
+        let entity = commands
+            .spawn((
+                PbrBundle {
+                    mesh: cube_meshes[&leaf.size].clone(),
+                    material: lithology_materials[lithology_index][jointed as usize].clone(),
+                    transform,
+                    ..default()
+                },
+                RigidBody::Dynamic,
+                collider,
+                friction,
+                restitution,
+                ColliderMassProperties::Mass(mass),
+                BenchBlock,
+                Block {
+                    origin: leaf.min,
+                    original_position: transform.translation,
+                    size: voxel_dimensions * leaf.size as f32,
+                    mass,
+                    lithology: lithology_index,
+                    grade: rock_mass.lithologies[lithology_index].grade,
+                    displaced_by: None,
+                    max_impulse: 0.0,
+                    peak_speed: 0.0,
+                },
+            ))
+            .id();
+        for x in leaf.min.x..(leaf.min.x + leaf.size).min(voxels.counts.x) {
+            for y in leaf.min.y..(leaf.min.y + leaf.size).min(voxels.counts.y) {
+                for z in leaf.min.z..(leaf.min.z + leaf.size).min(voxels.counts.z) {
+                    let index = voxels.index(UVec3::new(x, y, z));
+                    owners[index] = Some((entity, lithology_index, transform.translation));
+                }
+            }
+        }
+    }
+
+    if !bonds.enabled {
+        return;
+    }
+    // bond every block to the blocks across its +x, +y and +z faces, holes left by carving stay
+    // unbonded. A merged block can touch several smaller ones and the other way round, so the
+    // shared faces are summed voxel by voxel
+    struct Contact {
+        area: f32,
+        point_sum: Vec3, // sum of the voxel face centers, averaged into the anchor
+        voxel_faces: usize,
+        voxel_pair: (Vec3, Vec3), // one pair of touching voxels for looking up joints
+    }
+    let neighbours = [
+        (UVec3::X, y_res * z_res),
+        (UVec3::Y, x_res * z_res),
+        (UVec3::Z, x_res * y_res),
+    ];
+    let mut contacts: Vec<((Entity, usize, Vec3), (Entity, usize, Vec3), Contact)> = Vec::new();
+    let mut contact_index: HashMap<(Entity, Entity), usize> = HashMap::new();
+    for x in 0..voxels.counts.x {
+        for y in 0..voxels.counts.y {
+            for z in 0..voxels.counts.z {
+                let voxel = UVec3::new(x, y, z);
+                let Some(owner_a) = owners[voxels.index(voxel)] else {
+                    continue;
+                };
+                for (step, area) in neighbours {
+                    let neighbour = voxel + step;
+                    if !neighbour.cmplt(voxels.counts).all() {
+                        continue;
+                    }
+                    let Some(owner_b) = owners[voxels.index(neighbour)] else {
+                        continue;
+                    };
+                    if owner_a.0 == owner_b.0 {
+                        continue;
+                    }
+                    let (center_a, center_b) = (voxel_center(voxel), voxel_center(neighbour));
+                    let index = *contact_index
+                        .entry((owner_a.0, owner_b.0))
+                        .or_insert_with(|| {
+                            contacts.push((
+                                owner_a,
+                                owner_b,
+                                Contact {
+                                    area: 0.0,
+                                    point_sum: Vec3::ZERO,
+                                    voxel_faces: 0,
+                                    voxel_pair: (center_a, center_b),
+                                },
+                            ));
+                            contacts.len() - 1
+                        });
+                    let contact = &mut contacts[index].2;
+                    contact.area += area;
+                    contact.point_sum += (center_a + center_b) / 2.0;
+                    contact.voxel_faces += 1;
+                }
+            }
+        }
+    }
+    for ((a, lithology_a, center_a), (b, lithology_b, center_b), contact) in contacts {
+        let strength_factor = rock_mass.lithologies[lithology_a]
+            .strength_factor()
+            .min(rock_mass.lithologies[lithology_b].strength_factor());
+        let face = rock_mass.face(contact.voxel_pair.0, contact.voxel_pair.1);
+        let point = contact.point_sum / contact.voxel_faces as f32;
+        bond_blocks(
+            &mut commands,
+            a,
+            b,
+            point - center_a,
+            point - center_b,
+            contact.area,
+            &face,
+            strength_factor,
+            bonds,
+        );
+    }
+}
+
+// Fill the bench with Voronoi cells instead of cubes, seeds outside the bench or in a carved hole
+// spawn nothing but still cut their neighbours' cells
+#[allow(clippy::too_many_arguments)]
+fn setup_voronoi_bench(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    lithology_materials: &[[Handle<StandardMaterial>; 2]],
+    geometry: &BenchGeometry,
+    resolution: &[f32; 3],
+    drill_holes: &DrillHoles,
+    carve_drill_holes: bool,
+    settings: &VoronoiSettings,
+    rock_mass: &RockMass,
+    bonds: &BondSettings,
+    rng: &mut impl Rng,
+) {
+    // the box around the voxel grid of the same geometry
+    let (position, counts) = geometry.grid(resolution);
+    let resolution = Vec3::from(*resolution);
+    let min = position - resolution / 2.0;
+    let max = min + Vec3::new(counts[0] as f32, counts[1] as f32, counts[2] as f32) * resolution;
+    let block_size = resolution.min_element();
+
+    // the holes can have changed since the scenario was checked, and each one adds seeds
+    let seed_count = settings.seed_count(min, max, drill_holes);
+    if seed_count > MAX_BENCH_BLOCKS {
+        error!(
+            "voronoi would seed {} cells, the limit is {}. Nothing was spawned, use a larger \
+             cell_size or near_hole_cell_size",
+            seed_count, MAX_BENCH_BLOCKS
+        );
+        return;
+    }
+    let seeds = settings.scatter_seeds(
+        min,
+        max,
+        drill_holes,
+        |point| {
+            geometry.contains(point)
+                && !(carve_drill_holes && is_drill_hole(point, drill_holes, block_size))
+        },
+        rng,
     );
+    let cells = settings.build_cells(&seeds, min, max);
+
+    // the block of every seed, for bonding the cells across their shared faces
+    let mut blocks: Vec<Option<(Entity, usize, Vec3)>> = vec![None; seeds.len()];
+    for cell in cells.iter() {
+        let center = cell.centroid();
+        let extents = cell.extents();
+        let Some(collider) = Collider::convex_hull(
+            &cell
+                .vertices()
+                .map(|point| *point - center)
+                .collect::<Vec<_>>(),
+        ) else {
+            continue;
+        };
+        let lithology_index = rock_mass.lithology_at(center);
+        let lithology = &rock_mass.lithologies[lithology_index];
+        // the block slides on its weakest face
+        let faces = rock_mass.block_faces(center, extents);
+        let friction = faces
+            .iter()
+            .map(|face| face.friction)
+            .fold(lithology.friction, f32::min);
+        let jointed = faces.iter().any(|face| face.strength < 1.0);
+        let mass = lithology.density * cell.volume();
+        let entity = commands
+            .spawn((
+                PbrBundle {
+                    mesh: meshes.add(cell.mesh(center)),
+                    material: lithology_materials[lithology_index][jointed as usize].clone(),
+                    transform: Transform::from_translation(center),
+                    ..default()
+                },
+                RigidBody::Dynamic,
+                collider,
+                Friction::coefficient(friction),
+                Restitution::coefficient(lithology.restitution),
+                ColliderMassProperties::Mass(mass),
+                BenchBlock,
+                Block {
+                    origin: seeds[cell.seed].cell,
+                    original_position: center,
+                    size: extents,
+                    mass,
+                    lithology: lithology_index,
+                    grade: lithology.grade,
+                    displaced_by: None,
+                    max_impulse: 0.0,
+                    peak_speed: 0.0,
+                },
+            ))
+            .id();
+        blocks[cell.seed] = Some((entity, lithology_index, center));
+    }
+
+    if !bonds.enabled {
+        return;
+    }
+    // bond across every face two spawned cells share, once from the lower seed's side
+    for cell in cells.iter() {
+        let Some((a, lithology_a, center_a)) = blocks[cell.seed] else {
+            continue;
+        };
+        for face in cell.faces.iter() {
+            let Some(neighbour) = face.neighbour.filter(|neighbour| *neighbour > cell.seed) else {
+                continue;
+            };
+            let Some((b, lithology_b, center_b)) = blocks[neighbour] else {
+                continue;
+            };
+            let strength_factor = rock_mass.lithologies[lithology_a]
+                .strength_factor()
+                .min(rock_mass.lithologies[lithology_b].strength_factor());
+            let joint_face = rock_mass.face(seeds[cell.seed].position, seeds[neighbour].position);
+            let point = face.centroid();
+            bond_blocks(
+                commands,
+                a,
+                b,
+                point - center_a,
+                point - center_b,
+                face.area(),
+                &joint_face,
+                strength_factor,
+                bonds,
+            );
+        }
+    }
 }
@@ -0,0 +1,38 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+/// Every random draw of the simulation (detonator scatter, Voronoi seeds) comes from here, so a
+/// scenario with a `seed` plays out the same way every time
+#[derive(Resource)]
+pub struct SimulationRng {
+    pub rng: StdRng,
+}
+
+impl SimulationRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        SimulationRng {
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+        }
+    }
+
+    // start the sequence over for the next try, unseeded runs keep drawing new numbers
+    pub fn reset(&mut self, seed: Option<u64>) {
+        if let Some(seed) = seed {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+    }
+}
+
+/// How far the physics moved in the last frame in seconds. Fixed steps don't follow the frame
+/// time, so the blast clock and bond forces have to use the step too to stay reproducible
+pub fn physics_step_seconds(timestep_mode: &TimestepMode, time: &Time) -> f32 {
+    match timestep_mode {
+        TimestepMode::Fixed { dt, .. } => *dt,
+        _ => time.delta_seconds(),
+    }
+}
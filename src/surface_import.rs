@@ -0,0 +1,499 @@
+use crate::bench_geometry::BenchSurface;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on the samples of an imported surface
+const MAX_SURFACE_CELLS: usize = 4_000_000;
+
+fn default_height_scale() -> f32 {
+    1.0
+}
+
+fn default_z_up() -> bool {
+    true
+}
+
+/// How to read a survey file into a BenchSurface
+///
+/// Heightmaps (`csv`, `raw`, `png`) are grids of elevations starting at `origin`, one row per
+/// `cell_size` m along +z. Point clouds (`xyz`) and meshes (`obj`) are binned into cells of
+/// `cell_size`, keeping the highest point of each cell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurfaceImport {
+    #[serde(default)]
+    pub path: String, // file to read natively, relative to the scenario, the format is its extension
+    pub cell_size: f32,
+    pub floor: f32, // elevation of the bench floor
+    #[serde(default)]
+    pub origin: Vec3, // heightmaps: x, z of the first sample and the elevation of a 0 sample
+    #[serde(default = "default_height_scale")]
+    pub height_scale: f32, // heightmaps: elevation per unit of sample value
+    #[serde(default)]
+    pub columns: Option<usize>, // raw: samples per row
+    #[serde(default = "default_z_up")]
+    pub z_up: bool, // point clouds: survey axes with z as elevation and y as north
+    #[serde(default)]
+    pub offset: Vec3, // point clouds: added to every point after the axes are swapped
+}
+
+impl SurfaceImport {
+    /// Build the surface from the file contents, `format` is one of csv, raw, png, xyz or obj
+    pub fn read(&self, format: &str, bytes: &[u8]) -> Result<BenchSurface, String> {
+        if !(self.cell_size > 0.0) {
+            return Err(format!(
+                "cell_size must be positive, got {}",
+                self.cell_size
+            ));
+        }
+        let surface = match format.to_ascii_lowercase().as_str() {
+            "csv" => self.heightmap(read_csv(bytes)?),
+            "raw" => self.heightmap(self.read_raw(bytes)?),
+            "png" => self.heightmap(read_png(bytes)?),
+            "xyz" => self.point_cloud(&self.read_xyz(bytes)?, &[]),
+            "obj" => {
+                let (vertices, triangles) = self.read_obj(bytes)?;
+                self.point_cloud(&vertices, &triangles)
+            }
+            _ => return Err(format!("unknown surface format '{}'", format)),
+        }?;
+        surface.validate()?;
+        Ok(surface)
+    }
+
+    /// Read the file at `path`, relative paths are taken from `base_dir`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_path(&self, base_dir: &std::path::Path) -> Result<BenchSurface, String> {
+        let path = base_dir.join(&self.path);
+        let bytes = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let format = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        self.read(format, &bytes)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // grid of raw sample values, row by row
+    fn heightmap(
+        &self,
+        (columns, values): (usize, Vec<Option<f32>>),
+    ) -> Result<BenchSurface, String> {
+        if columns == 0 || values.len() % columns != 0 {
+            return Err("heightmap rows must all have the same length".to_string());
+        }
+        Ok(BenchSurface {
+            origin: self.origin,
+            cell_size: self.cell_size,
+            columns,
+            rows: values.len() / columns,
+            heights: values
+                .into_iter()
+                .map(|value| value.map(|value| self.origin.y + value * self.height_scale))
+                .collect(),
+            floor: self.floor,
+        })
+    }
+
+    // little endian 16 bit samples
+    fn read_raw(&self, bytes: &[u8]) -> Result<(usize, Vec<Option<f32>>), String> {
+        let columns = self
+            .columns
+            .ok_or("raw heightmaps need the number of columns")?;
+        if bytes.len() % 2 != 0 {
+            return Err("raw heightmaps must hold 16 bit samples".to_string());
+        }
+        let values = bytes
+            .chunks_exact(2)
+            .map(|sample| Some(u16::from_le_bytes([sample[0], sample[1]]) as f32))
+            .collect();
+        Ok((columns, values))
+    }
+
+    // survey coordinates to world coordinates
+    fn to_world(&self, point: Vec3) -> Vec3 {
+        let point = if self.z_up {
+            Vec3::new(point.x, point.z, -point.y)
+        } else {
+            point
+        };
+        point + self.offset
+    }
+
+    fn read_xyz(&self, bytes: &[u8]) -> Result<Vec<Vec3>, String> {
+        let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+        let mut points = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+            let values: Vec<f32> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .take(3)
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+            if values.len() < 3 {
+                return Err(format!("line {}: expected x y z", i + 1));
+            }
+            let point = self.to_world(Vec3::new(values[0], values[1], values[2]));
+            if !point.is_finite() {
+                return Err(format!("line {}: coordinates must be finite", i + 1));
+            }
+            points.push(point);
+        }
+        Ok(points)
+    }
+
+    // vertices and triangles, polygons are split into fans
+    fn read_obj(&self, bytes: &[u8]) -> Result<(Vec<Vec3>, Vec<[usize; 3]>), String> {
+        let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("v") => {
+                    let values: Vec<f32> = parts
+                        .take(3)
+                        .map(|value| value.parse::<f32>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("line {}: {}", i + 1, e))?;
+                    if values.len() < 3 {
+                        return Err(format!("line {}: expected v x y z", i + 1));
+                    }
+                    let vertex = self.to_world(Vec3::new(values[0], values[1], values[2]));
+                    if !vertex.is_finite() {
+                        return Err(format!("line {}: coordinates must be finite", i + 1));
+                    }
+                    vertices.push(vertex);
+                }
+                Some("f") => {
+                    // "f 1/1/1 2/2/2 3/3/3", only the vertex index matters, negative counts back
+                    let corners: Vec<usize> = parts
+                        .map(|corner| {
+                            let index: i64 = corner
+                                .split('/')
+                                .next()
+                                .unwrap_or_default()
+                                .parse()
+                                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+                            let index = if index < 0 {
+                                vertices.len() as i64 + index
+                            } else {
+                                index - 1
+                            };
+                            if index < 0 || index as usize >= vertices.len() {
+                                return Err(format!("line {}: no vertex {}", i + 1, corner));
+                            }
+                            Ok(index as usize)
+                        })
+                        .collect::<Result<_, String>>()?;
+                    for k in 2..corners.len() {
+                        triangles.push([corners[0], corners[k - 1], corners[k]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((vertices, triangles))
+    }
+
+    // Bin points into the grid keeping the highest per cell, then rasterize any triangles over it
+    // so meshes with large faces leave no gaps
+    fn point_cloud(
+        &self,
+        points: &[Vec3],
+        triangles: &[[usize; 3]],
+    ) -> Result<BenchSurface, String> {
+        if points.is_empty() {
+            return Err("the file holds no points".to_string());
+        }
+        let min = points
+            .iter()
+            .fold(Vec3::splat(f32::INFINITY), |a, b| a.min(*b));
+        let max = points
+            .iter()
+            .fold(Vec3::splat(f32::NEG_INFINITY), |a, b| a.max(*b));
+        // samples along a span, None when there are more than the limit allows on their own
+        let samples = |span: f32| {
+            let spacings = (span / self.cell_size).round();
+            (spacings < MAX_SURFACE_CELLS as f32).then(|| spacings as usize + 1)
+        };
+        let (Some(columns), Some(rows)) = (samples(max.x - min.x), samples(max.z - min.z)) else {
+            return Err(format!(
+                "surface would span {} x {} m, too much for {} m cells",
+                max.x - min.x,
+                max.z - min.z,
+                self.cell_size
+            ));
+        };
+        if columns
+            .checked_mul(rows)
+            .map_or(true, |cells| cells > MAX_SURFACE_CELLS)
+        {
+            return Err(format!(
+                "surface would have {} x {} cells, the limit is {}",
+                columns, rows, MAX_SURFACE_CELLS
+            ));
+        }
+        let mut heights: Vec<Option<f32>> = vec![None; columns * rows];
+        let cell = |x: f32, z: f32| {
+            let column = ((x - min.x) / self.cell_size).round() as usize;
+            let row = ((z - min.z) / self.cell_size).round() as usize;
+            row.min(rows - 1) * columns + column.min(columns - 1)
+        };
+        let raise = |heights: &mut Vec<Option<f32>>, index: usize, height: f32| {
+            heights[index] = Some(heights[index].map_or(height, |h| h.max(height)));
+        };
+
+        for point in points {
+            raise(&mut heights, cell(point.x, point.z), point.y);
+        }
+        for triangle in triangles {
+            let [a, b, c] = triangle.map(|index| points[index]);
+            let (a2, b2, c2) = (a.xz(), b.xz(), c.xz());
+            let area = (b2 - a2).perp_dot(c2 - a2);
+            if area.abs() < 1e-9 {
+                // vertical, already covered by its vertices
+                continue;
+            }
+            let low = a.min(b).min(c);
+            let high = a.max(b).max(c);
+            let first_column = ((low.x - min.x) / self.cell_size).ceil() as usize;
+            let last_column = ((high.x - min.x) / self.cell_size).floor() as usize;
+            let first_row = ((low.z - min.z) / self.cell_size).ceil() as usize;
+            let last_row = ((high.z - min.z) / self.cell_size).floor() as usize;
+            for row in first_row..=last_row.min(rows - 1) {
+                for column in first_column..=last_column.min(columns - 1) {
+                    let p = Vec2::new(
+                        min.x + column as f32 * self.cell_size,
+                        min.z + row as f32 * self.cell_size,
+                    );
+                    // barycentric weights of the cell center
+                    let wa = (b2 - p).perp_dot(c2 - p) / area;
+                    let wb = (c2 - p).perp_dot(a2 - p) / area;
+                    let wc = 1.0 - wa - wb;
+                    if wa < -1e-4 || wb < -1e-4 || wc < -1e-4 {
+                        continue;
+                    }
+                    raise(
+                        &mut heights,
+                        row * columns + column,
+                        wa * a.y + wb * b.y + wc * c.y,
+                    );
+                }
+            }
+        }
+
+        fill_gaps(&mut heights, columns, rows);
+        Ok(BenchSurface {
+            origin: Vec3::new(min.x, 0.0, min.z),
+            cell_size: self.cell_size,
+            columns,
+            rows,
+            heights,
+            floor: self.floor,
+        })
+    }
+}
+
+// Sparse clouds leave empty cells, give each one surrounded by data the mean of its neighbours so
+// the bench doesn't get pinholes. Cells at the edge of the data stay empty
+fn fill_gaps(heights: &mut [Option<f32>], columns: usize, rows: usize) {
+    let filled: Vec<Option<f32>> = (0..heights.len())
+        .map(|index| {
+            if heights[index].is_some() {
+                return heights[index];
+            }
+            let (row, column) = ((index / columns) as i64, (index % columns) as i64);
+            let mut neighbours = Vec::new();
+            for (dr, dc) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (r, c) = (row + dr, column + dc);
+                if r < 0 || c < 0 || r >= rows as i64 || c >= columns as i64 {
+                    return None;
+                }
+                neighbours.push(heights[(r as usize) * columns + c as usize]?);
+            }
+            Some(neighbours.iter().sum::<f32>() / neighbours.len() as f32)
+        })
+        .collect();
+    heights.copy_from_slice(&filled);
+}
+
+// one row of comma (or whitespace) separated elevations per line, empty values have no data
+fn read_csv(bytes: &[u8]) -> Result<(usize, Vec<Option<f32>>), String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+    let mut columns = 0;
+    let mut values = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row: Vec<Option<f32>> = line
+            .split(',')
+            .map(|value| match value.trim() {
+                "" | "nan" | "NaN" => Ok(None),
+                value => value.parse::<f32>().map(Some),
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if columns == 0 {
+            columns = row.len();
+        } else if row.len() != columns {
+            return Err(format!(
+                "line {}: {} values, the first row has {}",
+                i + 1,
+                row.len(),
+                columns
+            ));
+        }
+        values.extend(row);
+    }
+    Ok((columns, values))
+}
+
+// grayscale (or the first channel of colour) 8 or 16 bit png, transparent pixels have no data
+fn read_png(bytes: &[u8]) -> Result<(usize, Vec<Option<f32>>), String> {
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+    let samples = info.color_type.samples();
+    let bytes_per_sample = match info.bit_depth {
+        png::BitDepth::Sixteen => 2,
+        _ => 1,
+    };
+    let has_alpha = matches!(
+        info.color_type,
+        png::ColorType::GrayscaleAlpha | png::ColorType::Rgba
+    );
+    let sample = |pixel: &[u8], channel: usize| -> f32 {
+        let start = channel * bytes_per_sample;
+        if bytes_per_sample == 2 {
+            u16::from_be_bytes([pixel[start], pixel[start + 1]]) as f32
+        } else {
+            pixel[start] as f32
+        }
+    };
+    let values = buffer[..info.buffer_size()]
+        .chunks_exact(samples * bytes_per_sample)
+        .map(|pixel| {
+            if has_alpha && sample(pixel, samples - 1) == 0.0 {
+                None
+            } else {
+                Some(sample(pixel, 0))
+            }
+        })
+        .collect();
+    Ok((info.width as usize, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import() -> SurfaceImport {
+        SurfaceImport {
+            path: String::new(),
+            cell_size: 1.0,
+            floor: 0.0,
+            origin: Vec3::ZERO,
+            height_scale: default_height_scale(),
+            columns: None,
+            z_up: default_z_up(),
+            offset: Vec3::ZERO,
+        }
+    }
+
+    #[test]
+    fn csv_rows_with_gaps() {
+        let mut import = import();
+        import.origin.y = 100.0;
+        import.height_scale = 2.0;
+        let surface = import.read("csv", b"1,2,3\n4,,6\n").unwrap();
+        assert_eq!((surface.columns, surface.rows), (3, 2));
+        assert_eq!(
+            surface.heights,
+            vec![
+                Some(102.0),
+                Some(104.0),
+                Some(106.0),
+                Some(108.0),
+                None,
+                Some(112.0)
+            ]
+        );
+        assert!(import.read("csv", b"1,2,3\n4,5\n").is_err());
+    }
+
+    #[test]
+    fn raw_little_endian_samples() {
+        let mut import = import();
+        assert!(import.read("raw", &[1, 0, 0, 1]).is_err());
+        import.columns = Some(2);
+        let surface = import.read("raw", &[1, 0, 0, 1]).unwrap();
+        assert_eq!(surface.heights, vec![Some(1.0), Some(256.0)]);
+    }
+
+    #[test]
+    fn png_transparent_pixels_have_no_data() {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[50, 255, 0, 0]).unwrap();
+        writer.finish().unwrap();
+        let surface = import().read("png", &bytes).unwrap();
+        assert_eq!(surface.heights, vec![Some(50.0), None]);
+    }
+
+    #[test]
+    fn xyz_swaps_axes_and_fills_gaps() {
+        // a 3 x 3 grid of survey points sloping up to the east, the middle one missing
+        let mut text = String::new();
+        for x in 0..3 {
+            for y in 0..3 {
+                if (x, y) != (1, 1) {
+                    text += &format!("{} {} {}\n", x, y, 10 + x);
+                }
+            }
+        }
+        let surface = import().read("xyz", text.as_bytes()).unwrap();
+        // north is -z, so the grid starts 2 m south of the survey origin
+        assert_eq!(surface.origin, Vec3::new(0.0, 0.0, -2.0));
+        assert_eq!((surface.columns, surface.rows), (3, 3));
+        assert_eq!(surface.heights[3], Some(10.0));
+        // the mean of 10, 11, 11 and 12 around it
+        assert_eq!(surface.heights[4], Some(11.0));
+        assert!(import().read("xyz", b"1 2\n").is_err());
+    }
+
+    #[test]
+    fn point_clouds_must_be_finite_and_bounded() {
+        assert!(import().read("xyz", b"0 0 1\ninf 0 1\n").is_err());
+        assert!(import().read("obj", b"v 0 1 0\nv 1e39 1 0\n").is_err());
+        // finite, but far too many 1 m cells between them
+        assert!(import()
+            .read("xyz", b"0 0 1\n3e38 0 1\n-3e38 0 1\n")
+            .is_err());
+        assert!(import().read("xyz", b"0 0 1\n1e5 1e5 1\n").is_err());
+    }
+
+    #[test]
+    fn obj_triangles_are_rasterized() {
+        let mut import = import();
+        import.z_up = false;
+        // two triangles over 4 x 4 m rising 1 m per m along x
+        let text = "v 0 0 0\nv 4 4 0\nv 4 4 4\nv 0 0 4\nf 1 2 3\nf 1/1 3/3 -1\n";
+        let surface = import.read("obj", text.as_bytes()).unwrap();
+        assert_eq!((surface.columns, surface.rows), (5, 5));
+        assert!(surface.heights.iter().all(|height| height.is_some()));
+        let height = surface.heights[2 * 5 + 2].unwrap();
+        assert!((height - 2.0).abs() < 1e-4);
+        assert!(import.read("obj", b"v 0 0 0\nf 1 2 3\n").is_err());
+    }
+}
@@ -1,45 +1,353 @@
+use crate::explosives::{ExplosiveCharge, ExplosiveLibrary, HoleInterval};
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
+/// Largest detonator scatter a hole may have in milliseconds, far beyond any real detonator
+const MAX_SCATTER: f32 = 1000.0;
+
+fn default_hole_diameter() -> f32 {
+    0.115
+}
+
+fn default_hole_dip() -> f32 {
+    90.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DrillHole {
     // properties of a singular drill hole
-    pub position: Vec3, // position of the center of the drill hole
-    pub radius: f32,    // radius of the cylindrical area
-    pub height: f32,    // height of the cylindrical area
-    pub timing: f32,    // time in milliseconds
+    #[serde(default)]
+    pub id: u32, // stable id used by the editor, 0 until the hole is added to DrillHoles
+    pub collar: Vec3, // position of the top of the hole
+    #[serde(default)]
+    pub azimuth: f32, // bearing of the hole in degrees clockwise from north (-z)
+    #[serde(default = "default_hole_dip")]
+    pub dip: f32, // angle below horizontal in degrees, 90 is vertical
+    pub length: f32,  // length of the hole along its axis from the collar down to grade
+    #[serde(default)]
+    pub subdrill: f32, // extra length drilled past grade so the floor breaks cleanly
+    pub radius: f32,  // radius of the cylindrical area around the axis the blast can reach
+    pub timing: f32,  // delay of the hole in milliseconds
+    #[serde(default)]
+    pub surface_delay: f32, // surface delay reaching this hole in milliseconds, added to timing
+    #[serde(default)]
+    pub downhole_delay: f32, // in-hole detonator delay in milliseconds, added to timing
+    #[serde(default)]
+    pub scatter: f32, // detonator scatter, the firing time varies uniformly by +- this many ms
+    #[serde(default = "default_hole_diameter")]
+    pub diameter: f32, // drilled diameter in m
+    #[serde(default)]
+    pub charge: ExplosiveCharge, // charge decks and stemming / air intervals, at least one deck
 }
 
 impl DrillHole {
-    pub fn new(position: Vec3, radius: f32, height: f32, timing: f32) -> Self {
+    // vertical hole drilled down from `collar`
+    pub fn new(collar: Vec3, radius: f32, length: f32, timing: f32) -> Self {
         DrillHole {
-            position,
+            id: 0,
+            collar,
+            azimuth: 0.0,
+            dip: default_hole_dip(),
+            length,
+            subdrill: 0.0,
             radius,
-            height,
             timing,
+            surface_delay: 0.0,
+            downhole_delay: 0.0,
+            scatter: 0.0,
+            diameter: default_hole_diameter(),
+            // a third of the hole stemmed, the rest charged with ANFO
+            charge: ExplosiveCharge::single_deck("ANFO", length * 2.0 / 3.0, length / 3.0, length),
+        }
+    }
+
+    // unit vector pointing down the hole from the collar to the toe
+    pub fn direction(&self) -> Vec3 {
+        let azimuth = self.azimuth.to_radians();
+        let dip = self.dip.to_radians();
+        Vec3::new(
+            dip.cos() * azimuth.sin(),
+            -dip.sin(),
+            -dip.cos() * azimuth.cos(),
+        )
+    }
+
+    // rotation taking the y axis onto the hole axis, for cylinders built around y
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_arc(Vec3::NEG_Y, self.direction())
+    }
+
+    // length actually drilled, sub-drill included
+    pub fn drilled_length(&self) -> f32 {
+        self.length + self.subdrill
+    }
+
+    // bottom of the hole, below grade if the hole is sub-drilled
+    pub fn toe(&self) -> Vec3 {
+        self.point_at_depth(self.drilled_length())
+    }
+
+    // middle of the drilled hole, the center of its area of effect
+    pub fn center(&self) -> Vec3 {
+        self.point_at_depth(self.drilled_length() / 2.0)
+    }
+
+    // point on the hole axis `depth` m along the hole from the collar
+    pub fn point_at_depth(&self, depth: f32) -> Vec3 {
+        self.collar + self.direction() * depth
+    }
+
+    // distance from `point` to the nearest point of the hole axis between collar and toe
+    pub fn distance_to_axis(&self, point: Vec3) -> f32 {
+        let depth = (point - self.collar)
+            .dot(self.direction())
+            .clamp(0.0, self.drilled_length());
+        point.distance(self.point_at_depth(depth))
+    }
+
+    // closest point of an interval of this hole to `point`
+    pub fn closest_interval_point(&self, interval: &HoleInterval, point: Vec3) -> Vec3 {
+        let start = self.point_at_depth(interval.from);
+        let axis = self.point_at_depth(interval.to) - start;
+        let t = ((point - start).dot(axis) / axis.length_squared()).clamp(0.0, 1.0);
+        start + axis * t
+    }
+
+    // firing time in milliseconds before scatter
+    pub fn nominal_firing_time(&self) -> f32 {
+        self.timing + self.surface_delay + self.downhole_delay
+    }
+
+    // check the hole can be simulated, returns the reason if not
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.collar.is_finite() {
+            return Err("collar must be finite".to_string());
+        }
+        if !self.azimuth.is_finite() {
+            return Err(format!(
+                "azimuth must be a finite number, got {}",
+                self.azimuth
+            ));
+        }
+        if !(self.dip > 0.0 && self.dip <= 90.0) {
+            return Err(format!(
+                "dip must be between 0 and 90 degrees, got {}",
+                self.dip
+            ));
+        }
+        if !(self.radius > 0.0) {
+            return Err(format!("radius must be positive, got {}", self.radius));
+        }
+        if !(self.length > 0.0) {
+            return Err(format!("length must be positive, got {}", self.length));
+        }
+        if !(self.subdrill >= 0.0) {
+            return Err(format!(
+                "subdrill must not be negative, got {}",
+                self.subdrill
+            ));
+        }
+        if !(self.timing >= 0.0) {
+            return Err(format!("timing must not be negative, got {}", self.timing));
+        }
+        if !(self.surface_delay >= 0.0) {
+            return Err(format!(
+                "surface_delay must not be negative, got {}",
+                self.surface_delay
+            ));
+        }
+        if !(self.downhole_delay >= 0.0) {
+            return Err(format!(
+                "downhole_delay must not be negative, got {}",
+                self.downhole_delay
+            ));
+        }
+        if !(self.scatter >= 0.0 && self.scatter <= MAX_SCATTER) {
+            return Err(format!(
+                "scatter must be between 0 and {} ms, got {}",
+                MAX_SCATTER, self.scatter
+            ));
+        }
+        if !(self.diameter > 0.0) {
+            return Err(format!("diameter must be positive, got {}", self.diameter));
+        }
+        self.charge.validate(self.drilled_length())?;
+        // an unloaded hole would sit in the plan without ever going off
+        if self.charge.decks().next().is_none() {
+            return Err("charge must have at least one charge deck".to_string());
+        }
+        Ok(())
+    }
+
+    // validate and check every deck uses a product the library knows
+    pub fn validate_with(&self, library: &ExplosiveLibrary) -> Result<(), String> {
+        self.validate()?;
+        for (_, deck) in self.charge.decks() {
+            if library.get(&deck.product).is_none() {
+                return Err(format!("unknown explosive product '{}'", deck.product));
+            }
         }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone, Resource)]
-pub struct DrillHoles(pub Vec<DrillHole>);
+/// The holes of the blast. The second field is the next id to hand out, it only ever goes up so
+/// a removed hole's id is never given to a new one while the plan may still remember it
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DrillHoles(pub Vec<DrillHole>, #[serde(skip)] u32);
 
 impl DrillHoles {
     pub fn new(drill_holes: Vec<DrillHole>) -> Self {
-        DrillHoles(drill_holes)
+        let mut drill_holes = DrillHoles(drill_holes, 1);
+        drill_holes.assign_missing_ids();
+        drill_holes
+    }
+
+    // the counter isn't serialized and holes can come in with ids, so move it past all of them
+    fn skip_used_ids(&mut self) {
+        let max_id = self.0.iter().map(|drill_hole| drill_hole.id).max();
+        self.1 = self.1.max(max_id.unwrap_or(0) + 1);
+    }
+
+    // give every hole that came in without an id (id 0) a fresh one
+    pub fn assign_missing_ids(&mut self) {
+        self.skip_used_ids();
+        for drill_hole in self.0.iter_mut().filter(|drill_hole| drill_hole.id == 0) {
+            drill_hole.id = self.1;
+            self.1 += 1;
+        }
+    }
+
+    /// Swap in a whole new set of holes, those without an id get one after every id used so far
+    pub fn replace(&mut self, drill_holes: Vec<DrillHole>) {
+        self.0 = drill_holes;
+        self.assign_missing_ids();
+    }
+
+    pub fn get(&self, id: u32) -> Option<&DrillHole> {
+        self.0.iter().find(|drill_hole| drill_hole.id == id)
+    }
+
+    /// Add a hole and return the id it was stored under
+    pub fn add(&mut self, mut drill_hole: DrillHole) -> u32 {
+        self.skip_used_ids();
+        let id = self.1;
+        self.1 += 1;
+        drill_hole.id = id;
+        self.0.push(drill_hole);
+        id
+    }
+
+    /// Replace the hole with the given id, keeping the id. Returns false if there is no such hole
+    pub fn update(&mut self, id: u32, mut drill_hole: DrillHole) -> bool {
+        match self.0.iter_mut().find(|existing| existing.id == id) {
+            Some(existing) => {
+                drill_hole.id = id;
+                *existing = drill_hole;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<DrillHole> {
+        let index = self.0.iter().position(|drill_hole| drill_hole.id == id)?;
+        Some(self.0.remove(index))
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Component, Serialize)]
 pub struct Block {
-    // properties of a singular block element in the mesh
-    pub position: Vec3,
+    // properties of a singular block element in the mesh, fixed when the bench is built apart from
+    // what the blast did to it
+    // voxel index the block was spawned at, not unique: Voronoi blocks seeded in the same cell share it
+    pub origin: UVec3,
+    pub original_position: Vec3,
     pub size: Vec3,
     pub mass: f32,
+    pub lithology: usize,          // index into RockMass::lithologies
+    pub grade: f32,                // ore grade of the lithology, 0 for waste
+    pub displaced_by: Option<u32>, // id of the hole that gave the block its largest impulse
+    pub max_impulse: f32,          // that impulse in N s
+    pub peak_speed: f32,           // fastest the block has moved since the blast was fired, m/s
+}
+
+impl Block {
+    // where the block went, `translation` is its current position
+    pub fn movement(&self, translation: Vec3) -> Vec3 {
+        translation - self.original_position
+    }
+
+    // remember the hole if its impulse beats every impulse the block has had so far
+    pub fn record_impulse(&mut self, hole_id: u32, impulse: f32) {
+        if impulse > self.max_impulse {
+            self.max_impulse = impulse;
+            self.displaced_by = Some(hole_id);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RockJoint {
-    // basic implementation of the joint type for separating lithologies
-    pub positions: Vec<Vec3>,
+    // a single joint plane, see joints::JointSet for how they are generated
+    pub origin: Vec3, // any point on the plane
+    pub normal: Vec3, // unit normal of the plane
     pub friction: f32,
+    pub strength: f32, // fraction of the intact bond strength left across the joint
+}
+
+impl RockJoint {
+    // signed distance of `point` from the plane, positive on the normal's side
+    pub fn distance(&self, point: Vec3) -> f32 {
+        (point - self.origin).dot(self.normal)
+    }
+
+    // where the segment from `a` to `b` crosses the plane, if it does
+    pub fn intersection(&self, a: Vec3, b: Vec3) -> Option<Vec3> {
+        let (distance_a, distance_b) = (self.distance(a), self.distance(b));
+        if distance_a * distance_b > 0.0 || distance_a == distance_b {
+            return None;
+        }
+        Some(a + (b - a) * (distance_a / (distance_a - distance_b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hole() -> DrillHole {
+        DrillHole::new(Vec3::ZERO, 1.0, 6.0, 0.0)
+    }
+
+    #[test]
+    fn ids_are_not_reused() {
+        let mut drill_holes = DrillHoles::new(vec![hole(), hole()]);
+        assert_eq!(drill_holes.0[1].id, 2);
+        drill_holes.remove(2);
+        assert_eq!(drill_holes.add(hole()), 3);
+        drill_holes.replace(vec![hole()]);
+        assert_eq!(drill_holes.0[0].id, 4);
+    }
+
+    #[test]
+    fn ids_carry_on_after_loaded_ones() {
+        let json = serde_json::to_string(&DrillHoles::new(vec![hole(), hole()])).unwrap();
+        let mut drill_holes: DrillHoles = serde_json::from_str(&json).unwrap();
+        assert_eq!(drill_holes.add(hole()), 3);
+    }
+
+    #[test]
+    fn blocks_are_pushed_from_the_nearest_point_of_a_deck() {
+        let mut drill_hole = DrillHole::new(Vec3::new(0.0, 10.0, 0.0), 1.0, 10.0, 0.0);
+        drill_hole.charge = ExplosiveCharge::single_deck("ANFO", 5.0, 3.0, 10.0);
+        let deck = &drill_hole.charge.intervals[2];
+        // level with the stemming, above the deck
+        let point = drill_hole.closest_interval_point(deck, Vec3::new(1.0, 8.0, 0.0));
+        assert!(point.distance(Vec3::new(0.0, 5.0, 0.0)) < 1e-4);
+        // level with the deck
+        let point = drill_hole.closest_interval_point(deck, Vec3::new(1.0, 3.0, 0.0));
+        assert!(point.distance(Vec3::new(0.0, 3.0, 0.0)) < 1e-4);
+    }
 }
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+/// Scaled distance site law for ground vibration, PPV = K (D / √Q)^-β with D in m and Q the charge
+/// per delay in kg
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VibrationSettings {
+    pub site_constant: f32, // K in mm/s, fitted to the site's records, 1140 is a common first guess
+    pub attenuation: f32,   // β, how fast the vibration decays with scaled distance
+    pub delay_window: f32,  // ms, decks firing this close together or closer count as one delay
+}
+
+impl Default for VibrationSettings {
+    fn default() -> Self {
+        VibrationSettings {
+            site_constant: 1140.0,
+            attenuation: 1.6,
+            delay_window: 8.0,
+        }
+    }
+}
+
+impl VibrationSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.site_constant > 0.0) {
+            return Err(format!(
+                "site_constant must be positive, got {}",
+                self.site_constant
+            ));
+        }
+        if !(self.attenuation > 0.0) {
+            return Err(format!(
+                "attenuation must be positive, got {}",
+                self.attenuation
+            ));
+        }
+        if !(self.delay_window > 0.0) {
+            return Err(format!(
+                "delay_window must be positive, got {}",
+                self.delay_window
+            ));
+        }
+        Ok(())
+    }
+
+    /// Square root scaled distance in m/kg^0.5
+    pub fn scaled_distance(&self, distance: f32, charge: f32) -> f32 {
+        distance / charge.sqrt()
+    }
+
+    /// Peak particle velocity in mm/s `distance` m from `charge` kg firing at once
+    pub fn ppv(&self, distance: f32, charge: f32) -> f32 {
+        // a charge right under the monitor would be infinite, a metre is as close as the law goes
+        let scaled_distance = self.scaled_distance(distance.max(1.0), charge);
+        self.site_constant * scaled_distance.powf(-self.attenuation)
+    }
+}
+
+/// Predicted ground vibration at one monitor, from the delay that shakes it most
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VibrationResult {
+    pub ppv: f32,             // mm/s
+    pub time: f32,            // ms, nominal firing time of the first deck of that delay
+    pub charge: f32,          // kg firing in that delay
+    pub distance: f32,        // m to its closest deck
+    pub scaled_distance: f32, // m/kg^0.5
+    pub limit: Option<f32>,   // mm/s, the monitor's ppv_limit
+    pub exceeds_limit: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn site_law() {
+        let settings = VibrationSettings::default();
+        assert_eq!(settings.scaled_distance(100.0, 25.0), 20.0);
+        // 100 m from 100 kg is 10 m/kg^0.5, 1140 x 10^-1.6
+        assert!((settings.ppv(100.0, 100.0) - 28.6355).abs() < 1e-3);
+        // closer than a metre is taken as a metre
+        assert_eq!(settings.ppv(0.5, 1.0), settings.site_constant);
+    }
+}
@@ -0,0 +1,503 @@
+use crate::useful_structs::DrillHoles;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::utils::HashMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+fn default_near_hole_distance() -> f32 {
+    2.0
+}
+
+/// Irregular convex fragments instead of the cube grid
+///
+/// One seed is scattered in every `cell_size` cube of the bench, or one in every
+/// `near_hole_cell_size` cube within `near_hole_distance` of a drill hole, and each seed gets the
+/// part of the bench that is closer to it than to any other seed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoronoiSettings {
+    pub cell_size: f32, // average fragment size away from the holes
+    #[serde(default)]
+    pub near_hole_cell_size: Option<f32>, // smaller fragments around the holes
+    #[serde(default = "default_near_hole_distance")]
+    pub near_hole_distance: f32,
+}
+
+impl VoronoiSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.cell_size > 0.0) {
+            return Err(format!(
+                "cell_size must be positive, got {}",
+                self.cell_size
+            ));
+        }
+        if let Some(near_hole_cell_size) = self.near_hole_cell_size {
+            // at most 4 x 4 x 4 seeds per cell
+            if !(near_hole_cell_size >= self.cell_size / 4.0
+                && near_hole_cell_size <= self.cell_size)
+            {
+                return Err(format!(
+                    "near_hole_cell_size must be between a quarter of cell_size and cell_size, got {}",
+                    near_hole_cell_size
+                ));
+            }
+        }
+        if !(self.near_hole_distance >= 0.0) {
+            return Err(format!(
+                "near_hole_distance must not be negative, got {}",
+                self.near_hole_distance
+            ));
+        }
+        Ok(())
+    }
+
+    // seeds per side of the cell with its lowest corner at `corner`, more than one close to a hole
+    fn splits(&self, corner: Vec3, drill_holes: &DrillHoles) -> u32 {
+        let Some(size) = self.near_hole_cell_size else {
+            return 1;
+        };
+        let center = corner + Vec3::splat(self.cell_size / 2.0);
+        let half_diagonal = Vec3::splat(self.cell_size).length() / 2.0;
+        let near_hole = drill_holes.0.iter().any(|drill_hole| {
+            drill_hole.distance_to_axis(center) - half_diagonal < self.near_hole_distance
+        });
+        if near_hole {
+            (self.cell_size / size).round().max(1.0) as u32
+        } else {
+            1
+        }
+    }
+
+    // cells of `cell_size` covering the box from `min` to `max`
+    fn cell_counts(&self, min: Vec3, max: Vec3) -> UVec3 {
+        ((max - min) / self.cell_size)
+            .ceil()
+            .max(Vec3::ONE)
+            .as_uvec3()
+    }
+
+    /// Upper bound on the seeds scatter_seeds places in the same box, without drawing them
+    pub fn seed_count(&self, min: Vec3, max: Vec3, drill_holes: &DrillHoles) -> usize {
+        let counts = self.cell_counts(min, max);
+        let mut seed_count = 0;
+        for x in 0..counts.x {
+            for y in 0..counts.y {
+                for z in 0..counts.z {
+                    let corner = min + UVec3::new(x, y, z).as_vec3() * self.cell_size;
+                    seed_count += self.splits(corner, drill_holes).pow(3) as usize;
+                }
+            }
+        }
+        seed_count
+    }
+
+    /// Seeds inside the box from `min` to `max`. Seeds `is_solid` rejects still shape their
+    /// neighbours' cells, so the fragments follow the bench surface and carved holes
+    pub fn scatter_seeds(
+        &self,
+        min: Vec3,
+        max: Vec3,
+        drill_holes: &DrillHoles,
+        is_solid: impl Fn(Vec3) -> bool,
+        rng: &mut impl Rng,
+    ) -> Vec<VoronoiSeed> {
+        let counts = self.cell_counts(min, max);
+        let mut seeds = Vec::new();
+        for x in 0..counts.x {
+            for y in 0..counts.y {
+                for z in 0..counts.z {
+                    let cell = UVec3::new(x, y, z);
+                    let corner = min + cell.as_vec3() * self.cell_size;
+                    // split the cell into smaller ones close to a hole
+                    let splits = self.splits(corner, drill_holes);
+                    let sub_size = self.cell_size / splits as f32;
+                    for i in 0..splits {
+                        for j in 0..splits {
+                            for k in 0..splits {
+                                let jitter = Vec3::new(rng.gen(), rng.gen(), rng.gen());
+                                let position =
+                                    corner + (UVec3::new(i, j, k).as_vec3() + jitter) * sub_size;
+                                if position.cmpgt(max).any() {
+                                    continue;
+                                }
+                                seeds.push(VoronoiSeed {
+                                    position,
+                                    solid: is_solid(position),
+                                    cell,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        seeds
+    }
+
+    /// Cells of every solid seed, clipped to the box from `min` to `max`
+    pub fn build_cells(&self, seeds: &[VoronoiSeed], min: Vec3, max: Vec3) -> Vec<VoronoiCell> {
+        let bucket = |position: Vec3| ((position - min) / self.cell_size).floor().as_ivec3();
+        let mut buckets: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        for (i, seed) in seeds.iter().enumerate() {
+            buckets.entry(bucket(seed.position)).or_default().push(i);
+        }
+
+        let mut cells = Vec::new();
+        for (i, seed) in seeds.iter().enumerate() {
+            if !seed.solid {
+                continue;
+            }
+            // nearby seeds, closest first. Two buckets out covers every neighbour unless the seed
+            // sits in a much sparser part of the bench than the cell size
+            let home = bucket(seed.position);
+            let mut candidates: Vec<(f32, usize)> = Vec::new();
+            for dx in -2..=2 {
+                for dy in -2..=2 {
+                    for dz in -2..=2 {
+                        if let Some(others) = buckets.get(&(home + IVec3::new(dx, dy, dz))) {
+                            for &j in others.iter().filter(|j| **j != i) {
+                                candidates.push((seed.position.distance(seeds[j].position), j));
+                            }
+                        }
+                    }
+                }
+            }
+            candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut faces = box_faces(
+                min.max(seed.position - 2.0 * self.cell_size),
+                max.min(seed.position + 2.0 * self.cell_size),
+            );
+            for (distance, j) in candidates {
+                // a seed further than twice the furthest corner can't cut the cell any more
+                let reach = faces
+                    .iter()
+                    .flat_map(|face| face.polygon.iter())
+                    .map(|point| point.distance(seed.position))
+                    .fold(0.0, f32::max);
+                if distance > 2.0 * reach {
+                    break;
+                }
+                if distance <= f32::EPSILON {
+                    continue;
+                }
+                let normal = (seeds[j].position - seed.position) / distance;
+                let offset = normal.dot((seeds[j].position + seed.position) / 2.0);
+                faces = clip(faces, normal, offset, j);
+            }
+            if faces.len() >= 4 {
+                cells.push(VoronoiCell { seed: i, faces });
+            }
+        }
+        cells
+    }
+}
+
+/// A point a fragment grows around
+#[derive(Debug, Clone, Copy)]
+pub struct VoronoiSeed {
+    pub position: Vec3,
+    pub solid: bool, // false for seeds outside the bench or in a carved hole, they spawn nothing
+    pub cell: UVec3, // seeding cell it was scattered in
+}
+
+/// Face of a cell, `neighbour` is the seed on the other side or None on the outer box
+#[derive(Debug, Clone)]
+pub struct CellFace {
+    pub neighbour: Option<usize>,
+    pub polygon: Vec<Vec3>,
+}
+
+impl CellFace {
+    pub fn centroid(&self) -> Vec3 {
+        self.polygon.iter().sum::<Vec3>() / self.polygon.len() as f32
+    }
+
+    // vector normal to the face with the length of its area, pointing whichever way the winding
+    // says
+    fn area_vector(&self) -> Vec3 {
+        let first = self.polygon[0];
+        self.polygon
+            .windows(2)
+            .skip(1)
+            .map(|edge| (edge[0] - first).cross(edge[1] - first))
+            .sum::<Vec3>()
+            / 2.0
+    }
+
+    pub fn area(&self) -> f32 {
+        self.area_vector().length()
+    }
+}
+
+/// A convex fragment
+#[derive(Debug, Clone)]
+pub struct VoronoiCell {
+    pub seed: usize,
+    pub faces: Vec<CellFace>,
+}
+
+impl VoronoiCell {
+    pub fn vertices(&self) -> impl Iterator<Item = &Vec3> {
+        self.faces.iter().flat_map(|face| face.polygon.iter())
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        let count = self.vertices().count();
+        self.vertices().sum::<Vec3>() / count as f32
+    }
+
+    pub fn volume(&self) -> f32 {
+        let center = self.centroid();
+        self.faces
+            .iter()
+            .map(|face| {
+                let first = face.polygon[0] - center;
+                face.polygon
+                    .windows(2)
+                    .skip(1)
+                    .map(|edge| first.dot((edge[0] - center).cross(edge[1] - center)).abs())
+                    .sum::<f32>()
+                    / 6.0
+            })
+            .sum()
+    }
+
+    /// Size of the box around the cell
+    pub fn extents(&self) -> Vec3 {
+        let (min, max) = self.vertices().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), point| (min.min(*point), max.max(*point)),
+        );
+        max - min
+    }
+
+    /// Flat shaded mesh of the cell around `center`
+    pub fn mesh(&self, center: Vec3) -> Mesh {
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let cell_center = self.centroid();
+        for face in self.faces.iter() {
+            let mut normal = face.area_vector().normalize_or_zero();
+            let mut polygon = face.polygon.clone();
+            // wind every face counter clockwise seen from outside
+            if normal.dot(face.centroid() - cell_center) < 0.0 {
+                normal = -normal;
+                polygon.reverse();
+            }
+            let start = positions.len() as u32;
+            for point in polygon.iter() {
+                positions.push((*point - center).to_array());
+                normals.push(normal.to_array());
+            }
+            for k in 1..polygon.len() as u32 - 1 {
+                indices.extend([start, start + k, start + k + 1]);
+            }
+        }
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_indices(Indices::U32(indices))
+    }
+}
+
+// the six faces of an axis aligned box
+fn box_faces(min: Vec3, max: Vec3) -> Vec<CellFace> {
+    let corner = |x: bool, y: bool, z: bool| {
+        Vec3::new(
+            if x { max.x } else { min.x },
+            if y { max.y } else { min.y },
+            if z { max.z } else { min.z },
+        )
+    };
+    [
+        [
+            (false, false, false),
+            (false, false, true),
+            (false, true, true),
+            (false, true, false),
+        ],
+        [
+            (true, false, false),
+            (true, true, false),
+            (true, true, true),
+            (true, false, true),
+        ],
+        [
+            (false, false, false),
+            (true, false, false),
+            (true, false, true),
+            (false, false, true),
+        ],
+        [
+            (false, true, false),
+            (false, true, true),
+            (true, true, true),
+            (true, true, false),
+        ],
+        [
+            (false, false, false),
+            (false, true, false),
+            (true, true, false),
+            (true, false, false),
+        ],
+        [
+            (false, false, true),
+            (true, false, true),
+            (true, true, true),
+            (false, true, true),
+        ],
+    ]
+    .iter()
+    .map(|corners| CellFace {
+        neighbour: None,
+        polygon: corners.iter().map(|(x, y, z)| corner(*x, *y, *z)).collect(),
+    })
+    .collect()
+}
+
+// Keep the part of the cell where normal . point <= offset, the cut is closed with a new face
+// towards `neighbour`
+fn clip(faces: Vec<CellFace>, normal: Vec3, offset: f32, neighbour: usize) -> Vec<CellFace> {
+    const EPSILON: f32 = 1e-6;
+    let outside = |point: &Vec3| normal.dot(*point) - offset > EPSILON;
+    if !faces
+        .iter()
+        .flat_map(|face| face.polygon.iter())
+        .any(outside)
+    {
+        return faces;
+    }
+    let mut clipped = Vec::new();
+    let mut cut: Vec<Vec3> = Vec::new();
+    for face in faces {
+        let mut polygon = Vec::new();
+        for k in 0..face.polygon.len() {
+            let a = face.polygon[k];
+            let b = face.polygon[(k + 1) % face.polygon.len()];
+            let (da, db) = (normal.dot(a) - offset, normal.dot(b) - offset);
+            if da <= EPSILON {
+                polygon.push(a);
+            }
+            if (da <= EPSILON) != (db <= EPSILON) {
+                let point = a + (b - a) * (da / (da - db));
+                polygon.push(point);
+                cut.push(point);
+            }
+        }
+        if polygon.len() >= 3 {
+            clipped.push(CellFace {
+                neighbour: face.neighbour,
+                polygon,
+            });
+        }
+    }
+
+    // the cut points lie on a convex polygon, order them by angle around its middle
+    if cut.len() >= 3 {
+        let middle = cut.iter().sum::<Vec3>() / cut.len() as f32;
+        let u = normal.any_orthonormal_vector();
+        let v = normal.cross(u);
+        cut.sort_by(|a, b| {
+            let angle = |point: &Vec3| (*point - middle).dot(v).atan2((*point - middle).dot(u));
+            angle(a).total_cmp(&angle(b))
+        });
+        cut.dedup_by(|a, b| a.distance(*b) < EPSILON);
+        if cut.len() > 1 && cut[0].distance(cut[cut.len() - 1]) < EPSILON {
+            cut.pop();
+        }
+        if cut.len() >= 3 {
+            clipped.push(CellFace {
+                neighbour: Some(neighbour),
+                polygon: cut,
+            });
+        }
+    }
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::useful_structs::DrillHole;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn settings() -> VoronoiSettings {
+        VoronoiSettings {
+            cell_size: 1.0,
+            near_hole_cell_size: Some(0.5),
+            near_hole_distance: 0.0,
+        }
+    }
+
+    fn seed(position: Vec3, solid: bool) -> VoronoiSeed {
+        VoronoiSeed {
+            position,
+            solid,
+            cell: UVec3::ZERO,
+        }
+    }
+
+    #[test]
+    fn two_seeds_split_the_box_in_half() {
+        let seeds = [
+            seed(Vec3::new(0.5, 0.5, 0.5), true),
+            seed(Vec3::new(1.5, 0.5, 0.5), true),
+        ];
+        let cells = settings().build_cells(&seeds, Vec3::ZERO, Vec3::new(2.0, 1.0, 1.0));
+        assert_eq!(cells.len(), 2);
+        for cell in cells.iter() {
+            assert!((cell.volume() - 1.0).abs() < 1e-4);
+            assert!(cell.centroid().distance(seeds[cell.seed].position) < 1e-4);
+            assert!((cell.extents() - Vec3::ONE).abs().max_element() < 1e-4);
+            // one face against the other cell, the other five on the box
+            let shared: Vec<_> = cell
+                .faces
+                .iter()
+                .filter(|face| face.neighbour.is_some())
+                .collect();
+            assert_eq!(shared.len(), 1);
+            assert_eq!(shared[0].neighbour, Some(1 - cell.seed));
+            assert!((shared[0].area() - 1.0).abs() < 1e-4);
+            assert_eq!(cell.faces.len(), 6);
+        }
+    }
+
+    #[test]
+    fn seeds_that_are_not_solid_only_cut() {
+        let seeds = [
+            seed(Vec3::new(0.5, 0.5, 0.5), true),
+            seed(Vec3::new(1.5, 0.5, 0.5), false),
+        ];
+        let cells = settings().build_cells(&seeds, Vec3::ZERO, Vec3::new(2.0, 1.0, 1.0));
+        assert_eq!(cells.len(), 1);
+        assert!((cells[0].volume() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn seed_count_includes_the_cells_split_near_holes() {
+        // a vertical hole down the first column of a 3 x 3 x 3 box
+        let drill_holes = DrillHoles::new(vec![DrillHole::new(
+            Vec3::new(0.5, 3.0, 0.5),
+            1.0,
+            3.0,
+            0.0,
+        )]);
+        let settings = settings();
+        let (min, max) = (Vec3::ZERO, Vec3::splat(3.0));
+        // 3 cells split into 8 seeds each and 24 single ones
+        assert_eq!(settings.seed_count(min, max, &drill_holes), 48);
+        let mut rng = StdRng::seed_from_u64(7);
+        let seeds = settings.scatter_seeds(min, max, &drill_holes, |_| true, &mut rng);
+        assert_eq!(seeds.len(), 48);
+        assert!(seeds
+            .iter()
+            .all(|seed| seed.position.cmpge(min).all() && seed.position.cmple(max).all()));
+    }
+}
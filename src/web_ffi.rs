@@ -1,5 +1,18 @@
 // Import necessary modules and types
-use crate::bevy_app::init_app;
+use crate::bench_geometry::BenchGeometry;
+use crate::bevy_app::{init_app, init_app_with_scenario};
+use crate::blast_clock::{BlastClock, BlastCommand, BlastState};
+use crate::blast_pattern::BlastPattern;
+use crate::blast_report::{BlastReport, Monitor};
+use crate::bonds::BrokenBonds;
+use crate::explosives::ExplosiveLibrary;
+use crate::flyrock::FlyrockReport;
+use crate::fragmentation::FragmentationReport;
+use crate::kuz_ram::FragmentationPrediction;
+use crate::muckpile::MuckpileReport;
+use crate::scenario::BlastScenario;
+use crate::surface_import::SurfaceImport;
+use crate::useful_structs::{Block, DrillHole, DrillHoles};
 use crate::{canvas::*, canvas_view, create_canvas_window, ActiveInfo, WorkerApp};
 use bevy::app::PluginsState;
 use bevy::ecs::system::SystemState;
@@ -43,6 +56,20 @@ pub fn init_bevy_app() -> u64 {
     Box::into_raw(Box::new(app)) as u64
 }
 
+/// Create the app from a JSON blast scenario instead of the built-in defaults
+///
+/// Parse and validation errors are thrown back to JS as a string rather than panicking
+#[wasm_bindgen]
+pub fn init_bevy_app_with_scenario(json: &str) -> Result<u64, JsValue> {
+    let scenario = BlastScenario::from_json(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let mut app = init_app_with_scenario(scenario);
+    app.add_plugins(canvas_view::CanvasViewPlugin);
+
+    info!("init_bevy_app_with_scenario");
+
+    Ok(Box::into_raw(Box::new(app)) as u64)
+}
+
 // Create Canvas window
 #[wasm_bindgen]
 pub fn create_window_by_canvas(ptr: u64, canvas_id: &str, scale_factor: f32) {
@@ -188,6 +215,269 @@ pub fn set_auto_animation(ptr: u64, needs_animate: u32) {
     active_info.auto_animate = needs_animate > 0;
 }
 
+/// Add a drill hole from its JSON description, returns the id it was stored under
+#[wasm_bindgen]
+pub fn add_drill_hole(ptr: u64, json: &str) -> Result<u32, JsValue> {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    check_armed(app)?;
+    let drill_hole = parse_drill_hole(app, json)?;
+    let id = app
+        .world_mut()
+        .get_resource_mut::<DrillHoles>()
+        .unwrap()
+        .add(drill_hole);
+    drill_holes_changed(app);
+    Ok(id)
+}
+
+/// Replace the drill hole with the given id
+#[wasm_bindgen]
+pub fn update_drill_hole(ptr: u64, id: u32, json: &str) -> Result<(), JsValue> {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    check_armed(app)?;
+    let drill_hole = parse_drill_hole(app, json)?;
+    let mut drill_holes = app.world_mut().get_resource_mut::<DrillHoles>().unwrap();
+    if !drill_holes.update(id, drill_hole) {
+        return Err(JsValue::from_str(&format!("No drill hole with id {}", id)));
+    }
+    drill_holes_changed(app);
+    Ok(())
+}
+
+/// Remove the drill hole with the given id
+#[wasm_bindgen]
+pub fn remove_drill_hole(ptr: u64, id: u32) -> Result<(), JsValue> {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    check_armed(app)?;
+    let mut drill_holes = app.world_mut().get_resource_mut::<DrillHoles>().unwrap();
+    if drill_holes.remove(id).is_none() {
+        return Err(JsValue::from_str(&format!("No drill hole with id {}", id)));
+    }
+    drill_holes_changed(app);
+    Ok(())
+}
+
+/// Rebuild the bench under a survey surface, `format` is the file extension (csv, raw, png, xyz or
+/// obj) and `json` a SurfaceImport telling how to read it
+#[wasm_bindgen]
+pub fn import_bench_surface(
+    ptr: u64,
+    bytes: &[u8],
+    format: &str,
+    json: &str,
+) -> Result<(), JsValue> {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let import: SurfaceImport = serde_json::from_str(json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid surface import: {}", e)))?;
+    let surface = import
+        .read(format, bytes)
+        .map_err(|e| JsValue::from_str(&format!("Invalid surface: {}", e)))?;
+    let mut scenario = app.world().get_resource::<BlastScenario>().unwrap().clone();
+    scenario.geometry = Some(BenchGeometry::Surface(surface));
+    // the holes may have been edited since loading, check the ones the bench will be built with
+    scenario.drill_holes = app.world().get_resource::<DrillHoles>().unwrap().clone();
+    scenario
+        .validate()
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    app.insert_resource(scenario);
+    // the reset rebuilds the bench from the new geometry
+    app.world_mut().send_event(BlastCommand::Reset);
+    redraw(app);
+    Ok(())
+}
+
+/// Replace every drill hole with the holes of a generated pattern, returns how many were drilled
+#[wasm_bindgen]
+pub fn generate_pattern(ptr: u64, json: &str) -> Result<u32, JsValue> {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    check_armed(app)?;
+    let pattern: BlastPattern = serde_json::from_str(json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid pattern: {}", e)))?;
+    let generated = pattern
+        .generate()
+        .map_err(|e| JsValue::from_str(&format!("Invalid pattern: {}", e)))?;
+    let library = app.world().get_resource::<ExplosiveLibrary>().unwrap();
+    for drill_hole in generated.iter() {
+        drill_hole
+            .validate_with(library)
+            .map_err(|e| JsValue::from_str(&format!("Invalid pattern: {}", e)))?;
+    }
+    let count = generated.len() as u32;
+    // the holes are all this pattern's now, so the fragmentation prediction uses its design
+    let mut scenario = app.world_mut().get_resource_mut::<BlastScenario>().unwrap();
+    scenario.kuz_ram.burden = Some(pattern.burden);
+    scenario.kuz_ram.spacing = Some(pattern.spacing);
+    // replaced rather than inserted so the new holes don't take ids the plan has seen fire
+    app.world_mut()
+        .get_resource_mut::<DrillHoles>()
+        .unwrap()
+        .replace(generated);
+    drill_holes_changed(app);
+    Ok(count)
+}
+
+/// All drill holes as a JSON array
+#[wasm_bindgen]
+pub fn list_drill_holes(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let drill_holes = app.world().get_resource::<DrillHoles>().unwrap();
+    serde_json::to_string(drill_holes).unwrap()
+}
+
+/// All explosive products as a JSON array sorted by name, for the product dropdown
+#[wasm_bindgen]
+pub fn list_explosives(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let library = app.world().get_resource::<ExplosiveLibrary>().unwrap();
+    serde_json::to_string(&library.sorted()).unwrap()
+}
+
+/// Add (or replace by name) the products of a JSON array to the explosive library
+#[wasm_bindgen]
+pub fn load_explosive_library(ptr: u64, json: &str) -> Result<(), JsValue> {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let mut library = app
+        .world_mut()
+        .get_resource_mut::<ExplosiveLibrary>()
+        .unwrap();
+    library
+        .load_json(json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid explosive library: {}", e)))
+}
+
+/// Fire the blast, hole delays are counted from this moment
+#[wasm_bindgen]
+pub fn fire_blast(ptr: u64) {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    app.world_mut().send_event(BlastCommand::Fire);
+    redraw(app);
+}
+
+/// Freeze the blast clock and physics, `fire_blast` resumes
+#[wasm_bindgen]
+pub fn pause_blast(ptr: u64) {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    app.world_mut().send_event(BlastCommand::Pause);
+    redraw(app);
+}
+
+/// Rebuild the bench in its pre-blast state and re-arm
+#[wasm_bindgen]
+pub fn reset_blast(ptr: u64) {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    app.world_mut().send_event(BlastCommand::Reset);
+    redraw(app);
+}
+
+/// Blast clock state and elapsed milliseconds as JSON
+#[wasm_bindgen]
+pub fn get_blast_clock(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let clock = app.world().get_resource::<BlastClock>().unwrap();
+    serde_json::to_string(clock).unwrap()
+}
+
+/// Every bench block with its current position and how far it moved, as a JSON array
+#[wasm_bindgen]
+pub fn list_blocks(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let mut blocks_system_state: SystemState<Query<(&Block, &Transform)>> =
+        SystemState::from_world(app.world_mut());
+    let blocks: Vec<serde_json::Value> = blocks_system_state
+        .get(app.world())
+        .iter()
+        .map(|(block, transform)| {
+            serde_json::json!({
+                "block": block,
+                "position": transform.translation,
+                "movement": block.movement(transform.translation),
+            })
+        })
+        .collect();
+    serde_json::to_string(&blocks).unwrap()
+}
+
+/// Bonds broken since the bench was built or last reset, as a JSON array
+#[wasm_bindgen]
+pub fn get_broken_bonds(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let broken_bonds = app.world().get_resource::<BrokenBonds>().unwrap();
+    serde_json::to_string(broken_bonds).unwrap()
+}
+
+/// Size distribution of the muck as JSON, `time` stays null until every deck has fired and the
+/// blocks have come to rest
+#[wasm_bindgen]
+pub fn get_fragmentation_report(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let report = app.world().get_resource::<FragmentationReport>().unwrap();
+    report.to_json()
+}
+
+/// Kuz-Ram / Swebrec prediction for the current holes as JSON, null while no hole is charged.
+/// Available before firing, the fragmentation report carries the same numbers next to the result
+#[wasm_bindgen]
+pub fn get_fragmentation_prediction(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let prediction = app
+        .world()
+        .get_resource::<FragmentationPrediction>()
+        .unwrap();
+    serde_json::to_string(&prediction.0).unwrap()
+}
+
+/// Muckpile profile, throw, heave and swell as JSON, `time` stays null until the muck settles
+#[wasm_bindgen]
+pub fn get_muckpile_report(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let report = app.world().get_resource::<MuckpileReport>().unwrap();
+    report.to_json()
+}
+
+/// The muckpile section profiles as CSV, one row per sample
+#[wasm_bindgen]
+pub fn get_muckpile_csv(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let report = app.world().get_resource::<MuckpileReport>().unwrap();
+    report.to_csv()
+}
+
+/// Blocks flagged as flyrock so far as JSON, with their peak speed, distance and trail. Updated
+/// every frame while the blast runs
+#[wasm_bindgen]
+pub fn get_flyrock_report(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let report = app.world().get_resource::<FlyrockReport>().unwrap();
+    report.to_json()
+}
+
+/// Charge per delay, overlapping delays and the predicted vibration and airblast at every monitor
+/// as JSON, `within_limits` is false if any monitor is over one of its limits. Worked out from the
+/// holes, so it can be checked before firing
+#[wasm_bindgen]
+pub fn get_blast_report(ptr: u64) -> String {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let report = app.world().get_resource::<BlastReport>().unwrap();
+    report.to_json()
+}
+
+/// Replace the monitoring points with a JSON array of monitors
+#[wasm_bindgen]
+pub fn set_monitors(ptr: u64, json: &str) -> Result<(), JsValue> {
+    let app = unsafe { &mut *(ptr as *mut WorkerApp) };
+    let monitors: Vec<Monitor> = serde_json::from_str(json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid monitors: {}", e)))?;
+    for (i, monitor) in monitors.iter().enumerate() {
+        monitor
+            .validate()
+            .map_err(|e| JsValue::from_str(&format!("Invalid monitors[{}]: {}", i, e)))?;
+    }
+    let mut scenario = app.world_mut().get_resource_mut::<BlastScenario>().unwrap();
+    scenario.monitors = monitors;
+    redraw(app);
+    Ok(())
+}
+
 /// Frame rendering
 ///
 /// When render runs in a worker, the main thread might post a draw message before the current frame update is complete
@@ -237,6 +527,44 @@ pub fn release_app(ptr: u64) {
     crate::close_bevy_window(app);
 }
 
+/// Parse and validate a drill hole sent from JS
+fn parse_drill_hole(app: &WorkerApp, json: &str) -> Result<DrillHole, JsValue> {
+    let drill_hole: DrillHole = serde_json::from_str(json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid drill hole: {}", e)))?;
+    let library = app.world().get_resource::<ExplosiveLibrary>().unwrap();
+    drill_hole
+        .validate_with(library)
+        .map_err(|e| JsValue::from_str(&format!("Invalid drill hole: {}", e)))?;
+    Ok(drill_hole)
+}
+
+/// Holes can't change under a blast that has been fired, `reset_blast` re-arms it
+fn check_armed(app: &WorkerApp) -> Result<(), JsValue> {
+    if app.world().get_resource::<BlastClock>().unwrap().state != BlastState::Armed {
+        return Err(JsValue::from_str(
+            "Drill holes can only be edited while the blast is armed, reset it first",
+        ));
+    }
+    Ok(())
+}
+
+/// Carved holes are cut out of the bench when it is built, so it is rebuilt around the new ones
+fn drill_holes_changed(app: &mut WorkerApp) {
+    let scenario = app.world().get_resource::<BlastScenario>().unwrap();
+    if scenario.carve_drill_holes {
+        app.world_mut().send_event(BlastCommand::Reset);
+    }
+    redraw(app);
+}
+
+/// Make sure edits are drawn even when automatic animation is off
+fn redraw(app: &mut WorkerApp) {
+    // The window, and with it ActiveInfo, may not have been created yet
+    if let Some(mut active_info) = app.world_mut().get_resource_mut::<ActiveInfo>() {
+        active_info.remaining_frames = 10;
+    }
+}
+
 /// Convert JS array to Rust HashMap
 fn to_map(arr: js_sys::Array) -> HashMap<Entity, u64> {
     let mut map: HashMap<Entity, u64> = HashMap::new();